@@ -1,4 +1,11 @@
+use itertools::Itertools;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::cell_value::{compare_floats, CellValue};
 use super::config::Aggregate;
+use super::error::Error;
+use super::table::Table;
 
 #[derive(Clone, Copy)]
 pub enum Accumulator {
@@ -7,26 +14,253 @@ pub enum Accumulator {
     Count,
     Low,
     High,
+    Mean,
+    // holds the index of the column with the weights
+    WeightedMean(usize),
+    Median,
+    DistinctCount,
+    // the value shared by all of the rows, or null if they differ
+    Unique,
+    // the most common value, with ties going to the lowest
+    Dominant,
+    // the value of the row inserted first, and of the row written most recently (where
+    // updating a row counts as writing it), which hold the index of the column with the
+    // sequence numbers the rows are ordered by
+    First(usize),
+    Last(usize),
+    // the value of the row with the highest primary key, which holds the index of the
+    // key column, or None to use the order the rows were added in
+    LastByIndex(Option<usize>),
+    // the population or sample standard deviation and variance
+    StdDev { sample: bool },
+    Var { sample: bool },
+    // the sum of a group as a percentage of the sum of its parent group, or of all of
+    // the rows, which is filled in once the whole pivot tree has been aggregated
+    PctSumParent,
+    PctSumGrandTotal,
+    // the distinct values of a group, comma-separated and in ascending order, showing
+    // at most the given number of them
+    Join(usize),
+    // some (non-null) value of a group
+    Any,
+    // whether all, or any, of the values of a boolean column are true
+    And,
+    Or,
+}
+
+// the result of accumulating a group of rows, which can be merged with the state of
+// another group to give the result for both groups together
+#[derive(Clone)]
+pub enum AccumulatorState {
+    // sum, count, low, high and noop are their own state
+    Value(CellValue),
+    Mean { sum: f64, count: usize },
+    WeightedMean { sum: f64, weights: f64 },
+    // the values of the group, in ascending order
+    Sorted(Vec<f64>),
+    // the number of rows holding each (non-null) value
+    Counts(HashMap<CellValue, usize>),
+    // the value of the row picked by first or last, alongside the key it was picked by
+    Picked { key: CellValue, value: CellValue },
+    // the count, mean and sum of squared differences from the mean of the values,
+    // accumulated with Welford's algorithm and merged with Chan et al's
+    Moments { count: usize, mean: f64, m2: f64 },
 }
 
 impl Accumulator {
-    pub fn from_aggregate(agg: &Aggregate) -> Accumulator {
-        match agg {
+    pub fn from_aggregate(agg: &Aggregate, table: &Table) -> Result<Accumulator, Error> {
+        Ok(match agg {
             Aggregate::Sum => Accumulator::Sum,
             Aggregate::Count => Accumulator::Count,
             Aggregate::Low => Accumulator::Low,
             Aggregate::High => Accumulator::High,
+            Aggregate::Avg => Accumulator::Mean,
+            Aggregate::WeightedMean(column) => {
+                Accumulator::WeightedMean(table.index_for_column(column)?)
+            }
+            Aggregate::Median => Accumulator::Median,
+            Aggregate::DistinctCount => Accumulator::DistinctCount,
+            Aggregate::Unique => Accumulator::Unique,
+            Aggregate::Dominant => Accumulator::Dominant,
+            Aggregate::First => Accumulator::First(table.insertion_column()),
+            Aggregate::Last => Accumulator::Last(table.sequence_column()),
+            Aggregate::LastByIndex => Accumulator::LastByIndex(table.index_column()),
+            Aggregate::StdDevPop => Accumulator::StdDev { sample: false },
+            Aggregate::StdDevSample => Accumulator::StdDev { sample: true },
+            Aggregate::VarPop => Accumulator::Var { sample: false },
+            Aggregate::VarSample => Accumulator::Var { sample: true },
+            Aggregate::PctSumParent => Accumulator::PctSumParent,
+            Aggregate::PctSumGrandTotal => Accumulator::PctSumGrandTotal,
+            Aggregate::Join(limit) => Accumulator::Join(*limit),
+            Aggregate::Any => Accumulator::Any,
+            Aggregate::And => Accumulator::And,
+            Aggregate::Or => Accumulator::Or,
             Aggregate::Undefined => Accumulator::Noop,
-        }
+        })
     }
 
+    // the accumulator which combines the values of two groups, e.g. the counts of
+    // groups are summed
     pub fn total_accumulator(&self) -> Accumulator {
         match self {
-            Accumulator::Sum => Accumulator::Sum,
-            Accumulator::Count => Accumulator::Sum,
-            Accumulator::Low => Accumulator::Low,
-            Accumulator::High => Accumulator::High,
-            Accumulator::Noop => Accumulator::Noop,
+            Accumulator::Count | Accumulator::PctSumParent | Accumulator::PctSumGrandTotal => {
+                Accumulator::Sum
+            }
+            _ => *self,
+        }
+    }
+}
+
+// a value as a percentage of a total, which is null if either is null or the total is 0
+pub fn percentage(value: &CellValue, total: &CellValue) -> CellValue {
+    let as_f64 = |value: &CellValue| match value {
+        CellValue::Integer(value) => Some(*value as f64),
+        CellValue::Float(value) => Some(*value),
+        _ => None,
+    };
+    match (as_f64(value), as_f64(total)) {
+        (Some(value), Some(total)) if total != 0.0 => CellValue::Float(value / total * 100.0),
+        _ => CellValue::Null,
+    }
+}
+
+impl AccumulatorState {
+    pub fn merge(&self, other: &AccumulatorState, accumulator: &Accumulator) -> AccumulatorState {
+        use AccumulatorState::*;
+        match (self, other) {
+            (Value(a), Value(b)) => Value(a.accumulate(b, &accumulator.total_accumulator())),
+            (
+                Mean { sum, count },
+                Mean {
+                    sum: other_sum,
+                    count: other_count,
+                },
+            ) => Mean {
+                sum: sum + other_sum,
+                count: count + other_count,
+            },
+            (
+                WeightedMean { sum, weights },
+                WeightedMean {
+                    sum: other_sum,
+                    weights: other_weights,
+                },
+            ) => WeightedMean {
+                sum: sum + other_sum,
+                weights: weights + other_weights,
+            },
+            (Sorted(a), Sorted(b)) => Sorted(
+                a.iter()
+                    .merge_by(b.iter(), |a, b| {
+                        compare_floats(**a, **b) != Ordering::Greater
+                    })
+                    .cloned()
+                    .collect(),
+            ),
+            (Counts(a), Counts(b)) => {
+                let mut counts = a.clone();
+                for (value, count) in b {
+                    *counts.entry(value.clone()).or_insert(0) += count;
+                }
+                Counts(counts)
+            }
+            (Picked { key, .. }, Picked { key: other_key, .. }) => {
+                let ordering = match accumulator {
+                    Accumulator::First(_) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                if other_key.cmp(key) == ordering {
+                    other.clone()
+                } else {
+                    self.clone()
+                }
+            }
+            (Moments { count: 0, .. }, _) => other.clone(),
+            (_, Moments { count: 0, .. }) => self.clone(),
+            (
+                Moments { count, mean, m2 },
+                Moments {
+                    count: other_count,
+                    mean: other_mean,
+                    m2: other_m2,
+                },
+            ) => {
+                let total = count + other_count;
+                let delta = other_mean - mean;
+                Moments {
+                    count: total,
+                    mean: mean + delta * *other_count as f64 / total as f64,
+                    m2: m2
+                        + other_m2
+                        + delta * delta * (*count as f64) * (*other_count as f64) / total as f64,
+                }
+            }
+            // states are only merged with those of the same accumulator
+            _ => self.clone(),
+        }
+    }
+
+    pub fn value(&self, accumulator: &Accumulator) -> CellValue {
+        use AccumulatorState::*;
+        match self {
+            Value(value) => value.clone(),
+            Mean { count: 0, .. } => CellValue::Null,
+            Mean { sum, count } => CellValue::Float(sum / *count as f64),
+            WeightedMean { sum, weights } if *weights != 0.0 => CellValue::Float(sum / weights),
+            WeightedMean { .. } => CellValue::Null,
+            Sorted(values) if values.is_empty() => CellValue::Null,
+            Sorted(values) => {
+                let middle = values.len() / 2;
+                if values.len() % 2 == 1 {
+                    CellValue::Float(values[middle])
+                } else {
+                    CellValue::Float((values[middle - 1] + values[middle]) / 2.0)
+                }
+            }
+            Picked { value, .. } => value.clone(),
+            Moments { count, m2, .. } => {
+                let (sample, root) = match accumulator {
+                    Accumulator::StdDev { sample } => (*sample, true),
+                    Accumulator::Var { sample } => (*sample, false),
+                    _ => return CellValue::Null,
+                };
+                // the sample variance needs at least two values
+                let divisor = if sample {
+                    *count as f64 - 1.0
+                } else {
+                    *count as f64
+                };
+                if divisor <= 0.0 {
+                    return CellValue::Null;
+                }
+                let variance = m2 / divisor;
+                CellValue::Float(if root { variance.sqrt() } else { variance })
+            }
+            Counts(counts) => match accumulator {
+                Accumulator::DistinctCount => CellValue::Integer(counts.len() as i64),
+                Accumulator::Unique if counts.len() == 1 => counts.keys().next().unwrap().clone(),
+                Accumulator::Dominant => counts
+                    .iter()
+                    .max_by(|(a, a_count), (b, b_count)| {
+                        a_count.cmp(b_count).then_with(|| b.cmp(a))
+                    })
+                    .map_or(CellValue::Null, |(value, _)| value.clone()),
+                Accumulator::Join(_) if counts.is_empty() => CellValue::Null,
+                Accumulator::Join(limit) => {
+                    let mut values: Vec<&CellValue> = counts.keys().collect();
+                    values.sort();
+                    let mut joined: Vec<String> = values
+                        .iter()
+                        .take(*limit)
+                        .map(|value| value.label())
+                        .collect();
+                    if values.len() > *limit {
+                        joined.push("...".to_string());
+                    }
+                    CellValue::Str(joined.join(", "))
+                }
+                _ => CellValue::Null,
+            },
         }
     }
 }
@@ -3,76 +3,322 @@ use serde::ser::{Serialize, Serializer};
 use serde_json::Value;
 use std::cmp::{max, min, Eq, Ordering};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 use super::accumulator::Accumulator;
-use super::config::Operation;
+use super::config::{FilterValue, Operation};
+use super::schema::ColumnType;
+use super::temporal;
 
-#[derive(Eq, PartialEq, Clone)]
+#[derive(Clone, Debug)]
 pub enum CellValue {
     Integer(i64),
+    Float(f64),
     Str(String),
     Bool(bool),
+    // milliseconds since the unix epoch, truncated to midnight UTC
+    Date(i64),
+    // milliseconds since the unix epoch
+    Datetime(i64),
     Null,
 }
 
 impl CellValue {
-    pub fn new(serde_value: &Value) -> CellValue {
+    // converts a JSON value to a cell of the given column type, returning None if
+    // the value cannot be represented by that type
+    pub fn coerce(serde_value: &Value, column_type: &ColumnType) -> Option<CellValue> {
         use CellValue::*;
+        match (column_type, serde_value) {
+            (_, Value::Null) => Some(Null),
+            (ColumnType::Integer, Value::Number(value)) => match value.as_i64() {
+                Some(value) => Some(Integer(value)),
+                None => value
+                    .as_f64()
+                    .filter(|value| value.fract() == 0.0)
+                    .map(|value| Integer(value as i64)),
+            },
+            (ColumnType::Integer, Value::String(value)) => value.trim().parse().ok().map(Integer),
+            (ColumnType::Float, Value::Number(value)) => value.as_f64().map(Float),
+            (ColumnType::Float, Value::String(value)) => value.trim().parse().ok().map(Float),
+            (ColumnType::String, Value::String(value)) => Some(Str(value.clone())),
+            (ColumnType::String, Value::Number(value)) => Some(Str(value.to_string())),
+            (ColumnType::String, Value::Bool(value)) => Some(Str(value.to_string())),
+            (ColumnType::Boolean, Value::Bool(value)) => Some(Bool(*value)),
+            (ColumnType::Boolean, Value::String(value)) => match value.to_lowercase().as_str() {
+                "true" => Some(Bool(true)),
+                "false" => Some(Bool(false)),
+                _ => None,
+            },
+            // dates and datetimes are given as either ISO-8601 strings or epoch milliseconds
+            (ColumnType::Date, _) => {
+                Self::coerce_epoch_millis(serde_value).map(|v| Date(temporal::truncate_to_day(v)))
+            }
+            (ColumnType::Datetime, _) => Self::coerce_epoch_millis(serde_value).map(Datetime),
+            _ => None,
+        }
+    }
+
+    fn coerce_epoch_millis(serde_value: &Value) -> Option<i64> {
         match serde_value {
-            Value::Bool(value) => Bool(*value),
-            Value::Number(value) => Integer(value.as_i64().unwrap()),
-            Value::String(value) => Str(value.clone()),
-            _ => Null,
+            Value::String(value) => temporal::parse_datetime(value),
+            Value::Number(value) => value.as_i64().or_else(|| value.as_f64().map(|v| v as i64)),
+            _ => None,
         }
     }
 
-    pub fn matches(&self, operation: &Operation, value: &CellValue) -> bool {
+    // whether this cell satisfies a filter, where missing values only satisfy `is null`
+    // and values of different types are never equal
+    pub fn matches(&self, operation: &Operation, value: &FilterValue) -> bool {
         use CellValue::*;
         use Operation::*;
-        match (operation, value, self) {
-            (EqEq, Str(a), Str(b)) => a.eq(b),
-            (EqEq, Integer(a), Integer(b)) => a == b,
-            (EqEq, Bool(a), Bool(b)) => a == b,
-            _ => false,
+        let compare = |value: &CellValue| self.compare_to(value);
+        let scalar = match value {
+            FilterValue::Value(value) => Some(value),
+            FilterValue::List(_) => None,
+        };
+        match (operation, self) {
+            (IsNull, cell) => cell.is_null(),
+            (IsNotNull, cell) => !cell.is_null(),
+            (_, Null) => false,
+            (In, _) => value
+                .values()
+                .iter()
+                .any(|v| compare(v) == Some(Ordering::Equal)),
+            (NotIn, _) => !value
+                .values()
+                .iter()
+                .any(|v| compare(v) == Some(Ordering::Equal)),
+            (Contains, Str(a)) | (BeginsWith, Str(a)) | (EndsWith, Str(a)) => match scalar {
+                Some(Str(b)) => match operation {
+                    Contains => a.contains(b.as_str()),
+                    BeginsWith => a.starts_with(b.as_str()),
+                    _ => a.ends_with(b.as_str()),
+                },
+                _ => false,
+            },
+            (Contains, _) | (BeginsWith, _) | (EndsWith, _) => false,
+            (NotEq, _) => scalar.and_then(compare) != Some(Ordering::Equal),
+            (_, _) => match scalar.and_then(compare) {
+                Some(ordering) => match operation {
+                    EqEq => ordering == Ordering::Equal,
+                    Lt => ordering == Ordering::Less,
+                    LtEq => ordering != Ordering::Greater,
+                    Gt => ordering == Ordering::Greater,
+                    GtEq => ordering != Ordering::Less,
+                    _ => false,
+                },
+                None => false,
+            },
         }
     }
 
-    pub fn seed_value(&self, accumulator: &Accumulator) -> CellValue {
-        use Accumulator::*;
+    // compares this cell with a filter value, returning None if they have types which
+    // can't be compared, or either is NaN so that it satisfies none of the comparisons.
+    // Temporal cells are compared with ISO-8601 strings or epoch milliseconds, with
+    // dates compared at the resolution of a day.
+    fn compare_to(&self, value: &CellValue) -> Option<Ordering> {
         use CellValue::*;
-        match (accumulator, self) {
-            (High, Integer(value)) => Integer(*value),
-            (High, _) => Null,
-            (Low, Integer(value)) => Integer(*value),
-            (Low, _) => Null,
-            (Count, _) => Integer(1),
-            (Sum, Integer(value)) => Integer(*value),
-            (Sum, _) => Null,
-            (Noop, Bool(value)) => Bool(*value),
-            (Noop, Integer(value)) => Integer(*value),
-            (Noop, Str(value)) => Str(value.clone()),
-            (Noop, Null) => Null,
+        match (self, value) {
+            (Integer(a), Integer(b)) => Some(a.cmp(b)),
+            (Integer(_), Float(_)) | (Float(_), Integer(_)) | (Float(_), Float(_)) => {
+                self.as_f64().partial_cmp(&value.as_f64())
+            }
+            (Str(a), Str(b)) => Some(a.cmp(b)),
+            (Bool(a), Bool(b)) => Some(a.cmp(b)),
+            (Date(a), _) => value
+                .epoch_millis()
+                .map(|b| a.cmp(&temporal::truncate_to_day(b))),
+            (Datetime(a), _) => value.epoch_millis().map(|b| a.cmp(&b)),
+            _ => None,
         }
     }
 
+    // the text of a value, as used for the names of split columns and joined values,
+    // where dates and datetimes are written in ISO 8601 form
+    pub fn label(&self) -> String {
+        match self {
+            CellValue::Str(value) => value.clone(),
+            CellValue::Date(value) => temporal::format_date(*value),
+            CellValue::Datetime(value) => temporal::format_datetime(*value),
+            value => serde_json::to_string(value).unwrap(),
+        }
+    }
+
+    // the absolute value of numbers, for sorting by magnitude
+    pub fn magnitude(&self) -> CellValue {
+        match self {
+            CellValue::Integer(value) => CellValue::Integer(value.saturating_abs()),
+            CellValue::Float(value) => CellValue::Float(value.abs()),
+            value => value.clone(),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, CellValue::Null)
+    }
+
     pub fn accumulate(&self, other: &Self, operation: &Accumulator) -> CellValue {
         use Accumulator::*;
         use CellValue::*;
         match (operation, self, other) {
             (High, Integer(a), Integer(b)) => Integer(max(*a, *b)),
+            // mixing integers and floats promotes the result to a float
+            (High, Float(_), Float(_))
+            | (High, Integer(_), Float(_))
+            | (High, Float(_), Integer(_)) => Float(max_float(self.as_f64(), other.as_f64())),
+            (High, Date(a), Date(b)) => Date(max(*a, *b)),
+            (High, Datetime(a), Datetime(b)) => Datetime(max(*a, *b)),
             (High, Integer(a), _) => Integer(*a),
+            (High, Float(a), _) => Float(*a),
+            (High, Date(a), _) => Date(*a),
+            (High, Datetime(a), _) => Datetime(*a),
+            // groups without any values are skipped
+            (High, Null, _) => other.clone(),
             (High, _, _) => Null,
             (Low, Integer(a), Integer(b)) => Integer(min(*a, *b)),
+            (Low, Float(_), Float(_))
+            | (Low, Integer(_), Float(_))
+            | (Low, Float(_), Integer(_)) => Float(min_float(self.as_f64(), other.as_f64())),
+            (Low, Date(a), Date(b)) => Date(min(*a, *b)),
+            (Low, Datetime(a), Datetime(b)) => Datetime(min(*a, *b)),
             (Low, Integer(a), _) => Integer(*a),
+            (Low, Float(a), _) => Float(*a),
+            (Low, Date(a), _) => Date(*a),
+            (Low, Datetime(a), _) => Datetime(*a),
+            (Low, Null, _) => other.clone(),
             (Low, _, _) => Null,
             (Count, Integer(a), Null) => Integer(*a),
             (Count, Integer(a), _) => Integer(a + 1),
-            (Sum, Integer(a), Integer(b)) => Integer(a + b),
+            // sums which don't fit in an integer are promoted to floats
+            (Sum, Integer(a), Integer(b)) => a
+                .checked_add(*b)
+                .map_or(Float(*a as f64 + *b as f64), Integer),
+            (Sum, Float(_), Float(_))
+            | (Sum, Integer(_), Float(_))
+            | (Sum, Float(_), Integer(_)) => Float(self.as_f64() + other.as_f64()),
+            (Sum, Integer(_), Null) | (Sum, Float(_), Null) => self.clone(),
+            (Sum, Null, _) => other.clone(),
             (Sum, _, _) => Null,
             (Noop, _, _) => self.clone(),
+            (Any, Null, _) => other.clone(),
+            (Any, _, _) => self.clone(),
+            (And, Bool(a), Bool(b)) => Bool(*a && *b),
+            (Or, Bool(a), Bool(b)) => Bool(*a || *b),
+            (And, Null, Bool(_)) | (Or, Null, Bool(_)) => other.clone(),
+            (And, Bool(_), _) | (Or, Bool(_), _) => self.clone(),
             _ => Null,
         }
     }
+
+    // numeric value of integer and float cells, used when the two are combined
+    fn as_f64(&self) -> f64 {
+        match self {
+            CellValue::Integer(value) => *value as f64,
+            CellValue::Float(value) => *value,
+            _ => f64::NAN,
+        }
+    }
+
+    // whether a filter value can be compared with the cells of a column of the given
+    // type, see compare_to
+    pub fn is_comparable_with(&self, column_type: ColumnType) -> bool {
+        use CellValue::*;
+        match (column_type, self) {
+            (ColumnType::Integer | ColumnType::Float, Integer(_) | Float(_)) => true,
+            (ColumnType::String, Str(_)) => true,
+            (ColumnType::Boolean, Bool(_)) => true,
+            (ColumnType::Date | ColumnType::Datetime, value) => value.epoch_millis().is_some(),
+            _ => false,
+        }
+    }
+
+    // interprets a value as a point in time, used when comparing against temporal cells
+    fn epoch_millis(&self) -> Option<i64> {
+        match self {
+            CellValue::Integer(value) | CellValue::Date(value) | CellValue::Datetime(value) => {
+                Some(*value)
+            }
+            CellValue::Str(value) => temporal::parse_datetime(value),
+            _ => None,
+        }
+    }
+
+    // the relative ordering of values of different types
+    fn type_rank(&self) -> u8 {
+        use CellValue::*;
+        match self {
+            Null => 0,
+            Bool(_) => 1,
+            Integer(_) | Float(_) => 2,
+            Date(_) | Datetime(_) => 3,
+            Str(_) => 4,
+        }
+    }
+}
+
+// a total ordering over floats, NaN values are equal to each other and greater than any number
+pub fn compare_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+pub fn max_float(a: f64, b: f64) -> f64 {
+    match compare_floats(a, b) {
+        Ordering::Less => b,
+        _ => a,
+    }
+}
+
+pub fn min_float(a: f64, b: f64) -> f64 {
+    match compare_floats(a, b) {
+        Ordering::Greater => b,
+        _ => a,
+    }
+}
+
+impl PartialEq for CellValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CellValue {}
+
+// values which are equal must hash equally, so numbers are hashed via their float
+// representation and dates alongside datetimes
+impl Hash for CellValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use CellValue::*;
+        self.type_rank().hash(state);
+        match self {
+            Integer(value) => hash_float(*value as f64, state),
+            Float(value) => hash_float(*value, state),
+            Str(value) => value.hash(state),
+            Bool(value) => value.hash(state),
+            Date(value) | Datetime(value) => value.hash(state),
+            Null => {}
+        }
+    }
+}
+
+fn hash_float<H: Hasher>(value: f64, state: &mut H) {
+    float_bits(value).hash(state);
+}
+
+// the bits of a float, where floats which are equal have the same bits
+pub fn float_bits(value: f64) -> u64 {
+    if value.is_nan() {
+        f64::NAN.to_bits()
+    } else if value == 0.0 {
+        // treats -0.0 and 0.0 alike
+        0
+    } else {
+        value.to_bits()
+    }
 }
 
 impl PartialOrd for CellValue {
@@ -86,9 +332,17 @@ impl Ord for CellValue {
         use CellValue::*;
         match (self, other) {
             (Integer(a), Integer(b)) => a.cmp(b),
+            (Float(a), Float(b)) => compare_floats(*a, *b),
+            (Integer(a), Float(b)) => compare_floats(*a as f64, *b),
+            (Float(a), Integer(b)) => compare_floats(*a, *b as f64),
             (Str(a), Str(b)) => a.cmp(b),
             (Bool(a), Bool(b)) => a.cmp(b),
-            _ => Ordering::Greater,
+            (Date(a), Date(b))
+            | (Datetime(a), Datetime(b))
+            | (Date(a), Datetime(b))
+            | (Datetime(a), Date(b)) => a.cmp(b),
+            (Null, Null) => Ordering::Equal,
+            _ => self.type_rank().cmp(&other.type_rank()),
         }
     }
 }
@@ -100,8 +354,11 @@ impl Serialize for CellValue {
     {
         match self {
             CellValue::Integer(value) => serializer.serialize_i64(*value),
+            CellValue::Float(value) => serializer.serialize_f64(*value),
             CellValue::Str(value) => serializer.serialize_str(value.as_str()),
             CellValue::Bool(value) => serializer.serialize_bool(*value),
+            CellValue::Date(value) => serializer.serialize_i64(*value),
+            CellValue::Datetime(value) => serializer.serialize_i64(*value),
             CellValue::Null => serializer.serialize_none(),
         }
     }
@@ -116,6 +373,20 @@ impl<'de> Visitor<'de> for CellValueVisitor {
         formatter.write_str("not what we wanted")
     }
 
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(CellValue::Null)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(CellValue::Bool(v))
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
@@ -172,6 +443,20 @@ impl<'de> Visitor<'de> for CellValueVisitor {
         self.visit_i64(v as i64)
     }
 
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(CellValue::Float(v))
+    }
+
+    fn visit_f32<E>(self, v: f32) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.visit_f64(v as f64)
+    }
+
     fn visit_u8<E>(self, v: u8) -> Result<Self::Value, E>
     where
         E: de::Error,
@@ -188,3 +473,208 @@ impl<'de> Deserialize<'de> for CellValue {
         deserializer.deserialize_any(CellValueVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(cell: &CellValue, operation: Operation, value: CellValue) -> bool {
+        cell.matches(&operation, &FilterValue::Value(value))
+    }
+
+    fn hash(value: &CellValue) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn nan_is_ordered_after_every_number() {
+        use CellValue::*;
+        let mut values = [
+            Float(f64::NAN),
+            Integer(3),
+            Float(f64::INFINITY),
+            Float(-1.5),
+        ];
+        values.sort();
+        assert_eq!(values[..3], [Float(-1.5), Integer(3), Float(f64::INFINITY)]);
+        assert!(matches!(values[3], Float(value) if value.is_nan()));
+        assert_eq!(Float(f64::NAN), Float(f64::NAN));
+        assert_eq!(hash(&Float(f64::NAN)), hash(&Float(-f64::NAN)));
+    }
+
+    #[test]
+    fn integers_and_floats_are_compared_as_numbers() {
+        use CellValue::*;
+        assert_eq!(Integer(1), Float(1.0));
+        assert_eq!(hash(&Integer(1)), hash(&Float(1.0)));
+        assert!(Integer(2) > Float(1.5));
+        assert_eq!(
+            serde_json::from_str::<CellValue>("1.5").unwrap(),
+            Float(1.5)
+        );
+        assert!(matches!(
+            serde_json::from_str::<CellValue>("2").unwrap(),
+            Integer(2)
+        ));
+    }
+
+    #[test]
+    fn mixing_integers_and_floats_promotes_to_floats() {
+        use Accumulator::*;
+        use CellValue::*;
+        let accumulate = |a: CellValue, b: CellValue, accumulator| a.accumulate(&b, &accumulator);
+        assert!(matches!(accumulate(Integer(1), Float(0.5), Sum), Float(value) if value == 1.5));
+        assert!(matches!(accumulate(Float(0.5), Integer(1), High), Float(value) if value == 1.0));
+        assert!(matches!(accumulate(Integer(1), Float(0.5), Low), Float(value) if value == 0.5));
+        assert!(matches!(
+            accumulate(Integer(1), Integer(2), Sum),
+            Integer(3)
+        ));
+        assert!(matches!(accumulate(Null, Float(0.5), Sum), Float(value) if value == 0.5));
+    }
+
+    #[test]
+    fn dates_and_datetimes_are_parsed_ordered_and_aggregated() {
+        use Accumulator::*;
+        use CellValue::*;
+        let date = CellValue::coerce(&json!("2008-08-24T10:30:00Z"), &ColumnType::Date).unwrap();
+        let datetime =
+            CellValue::coerce(&json!("2008-08-24T10:30:00+01:00"), &ColumnType::Datetime).unwrap();
+        let millis = CellValue::coerce(&json!(1219573800000i64), &ColumnType::Datetime).unwrap();
+        assert!(matches!(date, Date(value) if value == 1219536000000));
+        assert!(matches!(datetime, Datetime(value) if value == 1219570200000));
+        assert!(matches!(millis, Datetime(value) if value == 1219573800000));
+        assert_eq!(
+            CellValue::coerce(&json!("24/08/2008"), &ColumnType::Date),
+            None
+        );
+
+        // dates and datetimes are ordered in time, after numbers and before strings
+        let mut values = vec![
+            millis.clone(),
+            Str("a".to_string()),
+            datetime.clone(),
+            date.clone(),
+            Integer(1),
+        ];
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                Integer(1),
+                date.clone(),
+                datetime.clone(),
+                millis.clone(),
+                Str("a".to_string())
+            ]
+        );
+
+        assert_eq!(datetime.accumulate(&millis, &High), millis);
+        assert_eq!(datetime.accumulate(&millis, &Low), datetime);
+        assert_eq!(Null.accumulate(&date, &Low), date);
+        assert!(matches!(Integer(1).accumulate(&date, &Count), Integer(2)));
+    }
+
+    #[test]
+    fn nan_satisfies_no_comparisons() {
+        let nan = CellValue::Float(f64::NAN);
+        for operation in [
+            Operation::Gt,
+            Operation::GtEq,
+            Operation::Lt,
+            Operation::LtEq,
+            Operation::EqEq,
+        ] {
+            assert!(!matches(&nan, operation, CellValue::Integer(1)));
+            assert!(!matches(&nan, operation, CellValue::Float(f64::NAN)));
+        }
+        assert!(matches(&nan, Operation::NotEq, CellValue::Integer(1)));
+    }
+
+    // whether a cell satisfies a filter, given as the operator and value the viewer sends
+    fn satisfies(cell: &CellValue, operation: &str, value: Value) -> bool {
+        let operation: Operation = serde_json::from_value(json!(operation)).unwrap();
+        let value: FilterValue = serde_json::from_value(value).unwrap();
+        cell.matches(&operation, &value)
+    }
+
+    #[test]
+    fn each_operator_compares_values_of_its_type() {
+        use CellValue::*;
+        let cases = [
+            (Integer(2), "==", json!(2), true),
+            (Integer(2), "==", json!(2.0), true),
+            (Integer(2), "!=", json!(3), true),
+            (Integer(2), "<", json!(2.5), true),
+            (Integer(2), "<=", json!(2), true),
+            (Float(2.5), ">", json!(2), true),
+            (Float(2.5), ">=", json!(2.5), true),
+            (Float(2.5), "<", json!(2), false),
+            (Str("Gold".to_string()), "==", json!("Gold"), true),
+            (Str("Gold".to_string()), "<", json!("Silver"), true),
+            (Str("Gold".to_string()), "contains", json!("ol"), true),
+            (Str("Gold".to_string()), "begins with", json!("Go"), true),
+            (Str("Gold".to_string()), "ends with", json!("Go"), false),
+            (Bool(true), "==", json!(true), true),
+            (Bool(false), "<", json!(true), true),
+            (
+                Date(1219536000000),
+                "==",
+                json!("2008-08-24T18:00:00Z"),
+                true,
+            ),
+            (Date(1219536000000), ">", json!("2008-08-23"), true),
+            (Datetime(1219573800000), "==", json!(1219573800000i64), true),
+            (
+                Datetime(1219573800000),
+                "<",
+                json!("2008-08-24T10:30:00+01:00"),
+                false,
+            ),
+            (Integer(2), "in", json!([1, 2, 3]), true),
+            (
+                Str("Gold".to_string()),
+                "in",
+                json!(["Silver", "Bronze"]),
+                false,
+            ),
+            (
+                Str("Gold".to_string()),
+                "not in",
+                json!(["Silver", "Bronze"]),
+                true,
+            ),
+            (Integer(2), "not in", json!([2]), false),
+            (Null, "is null", json!(null), true),
+            (Integer(2), "is null", json!(null), false),
+            (Integer(2), "is not null", json!(null), true),
+        ];
+        for (cell, operation, value, expected) in cases.iter() {
+            assert_eq!(
+                satisfies(cell, operation, value.clone()),
+                *expected,
+                "{:?} {} {}",
+                cell,
+                operation,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn values_of_other_types_and_missing_values_satisfy_no_comparisons() {
+        use CellValue::*;
+        for operation in ["==", "<", "<=", ">", ">=", "contains", "begins with", "in"].iter() {
+            assert!(!satisfies(&Integer(2), operation, json!("2")));
+            assert!(!satisfies(&Str("2".to_string()), operation, json!(2)));
+            assert!(!satisfies(&Bool(true), operation, json!(1)));
+            assert!(!satisfies(&Null, operation, json!(2)));
+        }
+        // which includes not being equal
+        assert!(satisfies(&Integer(2), "!=", json!("2")));
+        assert!(satisfies(&Integer(2), "not in", json!(["2"])));
+    }
+}
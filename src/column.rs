@@ -0,0 +1,547 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::accumulator::Accumulator;
+use super::cell_value::{compare_floats, float_bits, max_float, min_float, CellValue};
+use super::config::{FilterValue, Operation};
+use super::schema::ColumnType;
+
+// the values of a single column of a table, stored in a buffer of the column's type,
+// where a validity bitmap records which of the values are present (i.e. not null)
+pub struct Column {
+    values: ColumnValues,
+    validity: Bitmap,
+}
+
+enum ColumnValues {
+    Integer(Vec<i64>),
+    Float(Vec<f64>),
+    Boolean(Bitmap),
+    // each string is stored once in the dictionary, with the rows holding its code
+    Str(Dictionary, Vec<u32>),
+    Date(Vec<i64>),
+    Datetime(Vec<i64>),
+}
+
+// a value of a column which is cheap to copy, hash and compare for equality, where
+// strings are represented by their code in the column's dictionary and missing values
+// by None. Codes are only meaningful alongside the column they came from.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellCode(Option<u64>);
+
+// a packed sequence of bits
+#[derive(Default)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+// the distinct strings of a column, each identified by a code, where codes are given
+// out in the order the strings are first seen
+#[derive(Default)]
+pub struct Dictionary {
+    values: Vec<Rc<str>>,
+    codes: HashMap<Rc<str>, u32>,
+    // the position of each code when the strings are sorted, so that strings can be
+    // ordered by comparing integers
+    ranks: Vec<u32>,
+}
+
+impl Bitmap {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        if value {
+            self.words[index / 64] |= 1 << (index % 64);
+        } else {
+            self.words[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    pub fn push(&mut self, value: bool) {
+        if self.len == self.words.len() * 64 {
+            self.words.push(0);
+        }
+        self.len += 1;
+        self.set(self.len - 1, value);
+    }
+
+    // drops the bits which aren't kept
+    pub fn retain(&mut self, keep: &[bool]) {
+        let mut bitmap = Bitmap::default();
+        for (index, kept) in keep.iter().enumerate() {
+            if *kept {
+                bitmap.push(self.get(index));
+            }
+        }
+        *self = bitmap;
+    }
+}
+
+impl Dictionary {
+    pub fn value(&self, code: u32) -> &str {
+        &self.values[code as usize]
+    }
+
+    pub fn code(&self, value: &str) -> Option<u32> {
+        self.codes.get(value).copied()
+    }
+
+    // returns the code for the given string, adding it to the dictionary if it is new
+    pub fn intern(&mut self, value: &str) -> u32 {
+        if let Some(code) = self.code(value) {
+            return code;
+        }
+        let code = self.values.len() as u32;
+        let value: Rc<str> = Rc::from(value);
+        self.values.push(value.clone());
+        self.codes.insert(value, code);
+        code
+    }
+
+    // brings the ranks up to date with any strings which have been added
+    pub fn sort(&mut self) {
+        if self.ranks.len() == self.values.len() {
+            return;
+        }
+        let values = &self.values;
+        let mut order: Vec<u32> = (0..values.len() as u32).collect();
+        order.sort_by(|a, b| values[*a as usize].cmp(&values[*b as usize]));
+        self.ranks = vec![0; values.len()];
+        for (rank, code) in order.into_iter().enumerate() {
+            self.ranks[code as usize] = rank as u32;
+        }
+    }
+
+    pub fn rank(&self, code: u32) -> u32 {
+        self.ranks[code as usize]
+    }
+}
+
+impl Column {
+    pub fn new(column_type: ColumnType) -> Column {
+        let values = match column_type {
+            ColumnType::Integer => ColumnValues::Integer(vec![]),
+            ColumnType::Float => ColumnValues::Float(vec![]),
+            ColumnType::Boolean => ColumnValues::Boolean(Bitmap::default()),
+            ColumnType::String => ColumnValues::Str(Dictionary::default(), vec![]),
+            ColumnType::Date => ColumnValues::Date(vec![]),
+            ColumnType::Datetime => ColumnValues::Datetime(vec![]),
+        };
+        Column {
+            values,
+            validity: Bitmap::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validity.len()
+    }
+
+    pub fn is_valid(&self, row: usize) -> bool {
+        self.validity.get(row)
+    }
+
+    // the value of a row of a numeric column
+    pub fn as_f64(&self, row: usize) -> Option<f64> {
+        if !self.is_valid(row) {
+            return None;
+        }
+        match &self.values {
+            ColumnValues::Integer(values) => Some(values[row] as f64),
+            ColumnValues::Float(values) => Some(values[row]),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, row: usize) -> CellValue {
+        if !self.is_valid(row) {
+            return CellValue::Null;
+        }
+        match &self.values {
+            ColumnValues::Integer(values) => CellValue::Integer(values[row]),
+            ColumnValues::Float(values) => CellValue::Float(values[row]),
+            ColumnValues::Boolean(values) => CellValue::Bool(values.get(row)),
+            ColumnValues::Str(dictionary, codes) => {
+                CellValue::Str(dictionary.value(codes[row]).to_string())
+            }
+            ColumnValues::Date(values) => CellValue::Date(values[row]),
+            ColumnValues::Datetime(values) => CellValue::Datetime(values[row]),
+        }
+    }
+
+    pub fn code(&self, row: usize) -> CellCode {
+        if !self.is_valid(row) {
+            return CellCode(None);
+        }
+        CellCode(Some(match &self.values {
+            ColumnValues::Integer(values) => values[row] as u64,
+            ColumnValues::Float(values) => float_bits(values[row]),
+            ColumnValues::Boolean(values) => values.get(row) as u64,
+            ColumnValues::Str(_, codes) => codes[row] as u64,
+            ColumnValues::Date(values) | ColumnValues::Datetime(values) => values[row] as u64,
+        }))
+    }
+
+    // the code of a value which has been stored in this column
+    pub fn code_of(&self, value: &CellValue) -> CellCode {
+        CellCode(match (&self.values, value) {
+            (ColumnValues::Integer(_), CellValue::Integer(value)) => Some(*value as u64),
+            (ColumnValues::Float(_), CellValue::Float(value)) => Some(float_bits(*value)),
+            (ColumnValues::Boolean(_), CellValue::Bool(value)) => Some(*value as u64),
+            (ColumnValues::Str(dictionary, _), CellValue::Str(value)) => {
+                dictionary.code(value).map(|code| code as u64)
+            }
+            (ColumnValues::Date(_), CellValue::Date(value))
+            | (ColumnValues::Datetime(_), CellValue::Datetime(value)) => Some(*value as u64),
+            _ => None,
+        })
+    }
+
+    pub fn value_of(&self, code: CellCode) -> CellValue {
+        let code = match code.0 {
+            Some(code) => code,
+            None => return CellValue::Null,
+        };
+        match &self.values {
+            ColumnValues::Integer(_) => CellValue::Integer(code as i64),
+            ColumnValues::Float(_) => CellValue::Float(f64::from_bits(code)),
+            ColumnValues::Boolean(_) => CellValue::Bool(code != 0),
+            ColumnValues::Str(dictionary, _) => {
+                CellValue::Str(dictionary.value(code as u32).to_string())
+            }
+            ColumnValues::Date(_) => CellValue::Date(code as i64),
+            ColumnValues::Datetime(_) => CellValue::Datetime(code as i64),
+        }
+    }
+
+    // compares the values that two codes represent, in the same order as compare
+    pub fn compare_codes(&self, a: CellCode, b: CellCode) -> Ordering {
+        let (a, b) = match (a.0, b.0) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => (a, b),
+        };
+        match &self.values {
+            ColumnValues::Integer(_) | ColumnValues::Date(_) | ColumnValues::Datetime(_) => {
+                (a as i64).cmp(&(b as i64))
+            }
+            ColumnValues::Float(_) => compare_floats(f64::from_bits(a), f64::from_bits(b)),
+            ColumnValues::Boolean(_) => a.cmp(&b),
+            ColumnValues::Str(dictionary, _) => {
+                dictionary.rank(a as u32).cmp(&dictionary.rank(b as u32))
+            }
+        }
+    }
+
+    // sorts the dictionary of a string column, which must happen after new values are
+    // stored and before the column is next compared
+    pub fn sort_dictionary(&mut self) {
+        if let ColumnValues::Str(dictionary, _) = &mut self.values {
+            dictionary.sort();
+        }
+    }
+
+    // appends a value, which must either be null or of the same type as the column
+    pub fn push(&mut self, value: &CellValue) {
+        match &mut self.values {
+            ColumnValues::Integer(values) => values.push(0),
+            ColumnValues::Float(values) => values.push(0.0),
+            ColumnValues::Boolean(values) => values.push(false),
+            ColumnValues::Str(_, codes) => codes.push(0),
+            ColumnValues::Date(values) => values.push(0),
+            ColumnValues::Datetime(values) => values.push(0),
+        }
+        self.validity.push(false);
+        self.set(self.len() - 1, value);
+    }
+
+    // drops the rows which aren't kept, where the strings of the dropped rows stay in the
+    // dictionary so that the codes held by views remain valid
+    pub fn retain(&mut self, keep: &[bool]) {
+        match &mut self.values {
+            ColumnValues::Integer(values)
+            | ColumnValues::Date(values)
+            | ColumnValues::Datetime(values) => retain(values, keep),
+            ColumnValues::Float(values) => retain(values, keep),
+            ColumnValues::Boolean(values) => values.retain(keep),
+            ColumnValues::Str(_, codes) => retain(codes, keep),
+        }
+        self.validity.retain(keep);
+    }
+
+    // replaces a value, which must either be null or of the same type as the column
+    pub fn set(&mut self, row: usize, value: &CellValue) {
+        match (&mut self.values, value) {
+            (_, CellValue::Null) => {
+                self.validity.set(row, false);
+                return;
+            }
+            (ColumnValues::Integer(values), CellValue::Integer(value)) => values[row] = *value,
+            (ColumnValues::Float(values), CellValue::Float(value)) => values[row] = *value,
+            (ColumnValues::Boolean(values), CellValue::Bool(value)) => values.set(row, *value),
+            (ColumnValues::Str(dictionary, codes), CellValue::Str(value)) => {
+                codes[row] = dictionary.intern(value)
+            }
+            (ColumnValues::Date(values), CellValue::Date(value)) => values[row] = *value,
+            (ColumnValues::Datetime(values), CellValue::Datetime(value)) => values[row] = *value,
+            // values are coerced to the type of their column before they are stored
+            _ => panic!("value does not match the type of the column"),
+        }
+        self.validity.set(row, true);
+    }
+
+    // compares the magnitudes of the values of two rows of a numeric column, where the
+    // values of other columns are compared as they are
+    pub fn compare_abs(&self, a: usize, b: usize) -> Ordering {
+        match &self.values {
+            ColumnValues::Integer(_) | ColumnValues::Float(_) => {
+                self.get(a).magnitude().cmp(&self.get(b).magnitude())
+            }
+            _ => self.compare(a, b),
+        }
+    }
+
+    // compares the values of two rows, where missing values come first as with CellValue
+    pub fn compare(&self, a: usize, b: usize) -> Ordering {
+        match (self.is_valid(a), self.is_valid(b)) {
+            (false, false) => return Ordering::Equal,
+            (false, true) => return Ordering::Less,
+            (true, false) => return Ordering::Greater,
+            (true, true) => {}
+        }
+        match &self.values {
+            ColumnValues::Integer(values) => values[a].cmp(&values[b]),
+            ColumnValues::Float(values) => compare_floats(values[a], values[b]),
+            ColumnValues::Boolean(values) => values.get(a).cmp(&values.get(b)),
+            ColumnValues::Str(dictionary, codes) => {
+                dictionary.rank(codes[a]).cmp(&dictionary.rank(codes[b]))
+            }
+            ColumnValues::Date(values) | ColumnValues::Datetime(values) => {
+                values[a].cmp(&values[b])
+            }
+        }
+    }
+
+    // removes the rows which don't match the filter
+    pub fn filter(&self, rows: &mut Vec<usize>, operation: &Operation, value: &FilterValue) {
+        match &self.values {
+            // each string in the dictionary is matched once, then the rows are matched
+            // by code
+            ColumnValues::Str(dictionary, codes) => {
+                let matching: Vec<bool> = dictionary
+                    .values
+                    .iter()
+                    .map(|s| CellValue::Str(s.to_string()).matches(operation, value))
+                    .collect();
+                let null_matches = CellValue::Null.matches(operation, value);
+                rows.retain(|row| {
+                    if self.is_valid(*row) {
+                        matching[codes[*row] as usize]
+                    } else {
+                        null_matches
+                    }
+                });
+            }
+            _ => rows.retain(|row| self.matches(*row, operation, value)),
+        }
+    }
+
+    pub fn matches(&self, row: usize, operation: &Operation, value: &FilterValue) -> bool {
+        match (operation, &self.values, value) {
+            // strings are compared in place, rather than copied out of the dictionary
+            (
+                Operation::EqEq,
+                ColumnValues::Str(dictionary, codes),
+                FilterValue::Value(CellValue::Str(value)),
+            ) => self.is_valid(row) && dictionary.value(codes[row]) == value,
+            _ => self.get(row).matches(operation, value),
+        }
+    }
+
+    // aggregates the values of the given (non-empty) rows, for the accumulators which
+    // only need a single value as their state
+    pub fn aggregate(&self, rows: &[usize], accumulator: &Accumulator) -> CellValue {
+        let first = rows[0];
+        let valid_rows = || rows.iter().cloned().filter(move |row| self.is_valid(*row));
+        match accumulator {
+            // see RowAggregator
+            Accumulator::Mean
+            | Accumulator::WeightedMean(_)
+            | Accumulator::Median
+            | Accumulator::DistinctCount
+            | Accumulator::Unique
+            | Accumulator::Dominant
+            | Accumulator::First(_)
+            | Accumulator::Last(_)
+            | Accumulator::LastByIndex(_)
+            | Accumulator::StdDev { .. }
+            | Accumulator::Var { .. }
+            | Accumulator::PctSumParent
+            | Accumulator::PctSumGrandTotal
+            | Accumulator::Join(_) => CellValue::Null,
+            Accumulator::Noop => self.get(first),
+            Accumulator::Any => valid_rows()
+                .next()
+                .map_or(CellValue::Null, |row| self.get(row)),
+            Accumulator::And | Accumulator::Or => match &self.values {
+                ColumnValues::Boolean(values) if valid_rows().next().is_some() => {
+                    let mut bools = valid_rows().map(|row| values.get(row));
+                    CellValue::Bool(match accumulator {
+                        Accumulator::And => bools.all(|value| value),
+                        _ => bools.any(|value| value),
+                    })
+                }
+                _ => CellValue::Null,
+            },
+            // missing values are skipped, as with the other aggregates, and a group
+            // without any values has no sum, low or high
+            Accumulator::Count => CellValue::Integer(valid_rows().count() as i64),
+            Accumulator::Sum | Accumulator::Low | Accumulator::High
+                if valid_rows().next().is_none() =>
+            {
+                CellValue::Null
+            }
+            Accumulator::Sum => match &self.values {
+                // sums which don't fit in an integer are promoted to floats
+                ColumnValues::Integer(values) => valid_rows()
+                    .try_fold(0i64, |sum, row| sum.checked_add(values[row]))
+                    .map_or_else(
+                        || CellValue::Float(valid_rows().map(|row| values[row] as f64).sum()),
+                        CellValue::Integer,
+                    ),
+                ColumnValues::Float(values) => {
+                    CellValue::Float(valid_rows().map(|row| values[row]).sum())
+                }
+                _ => CellValue::Null,
+            },
+            Accumulator::Low => match &self.values {
+                ColumnValues::Integer(values) => {
+                    CellValue::Integer(valid_rows().map(|row| values[row]).min().unwrap())
+                }
+                ColumnValues::Float(values) => valid_rows()
+                    .map(|row| values[row])
+                    .reduce(min_float)
+                    .map_or(CellValue::Null, CellValue::Float),
+                ColumnValues::Date(values) => {
+                    CellValue::Date(valid_rows().map(|row| values[row]).min().unwrap())
+                }
+                ColumnValues::Datetime(values) => {
+                    CellValue::Datetime(valid_rows().map(|row| values[row]).min().unwrap())
+                }
+                _ => CellValue::Null,
+            },
+            Accumulator::High => match &self.values {
+                ColumnValues::Integer(values) => {
+                    CellValue::Integer(valid_rows().map(|row| values[row]).max().unwrap())
+                }
+                ColumnValues::Float(values) => valid_rows()
+                    .map(|row| values[row])
+                    .reduce(max_float)
+                    .map_or(CellValue::Null, CellValue::Float),
+                ColumnValues::Date(values) => {
+                    CellValue::Date(valid_rows().map(|row| values[row]).max().unwrap())
+                }
+                ColumnValues::Datetime(values) => {
+                    CellValue::Datetime(valid_rows().map(|row| values[row]).max().unwrap())
+                }
+                _ => CellValue::Null,
+            },
+        }
+    }
+}
+
+fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut keep = keep.iter();
+    values.retain(|_| keep.next() == Some(&true));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a string column, with its strings added out of order and some repeated
+    fn column(values: &[Option<&str>]) -> Column {
+        let mut column = Column::new(ColumnType::String);
+        for value in values {
+            column.push(&value.map_or(CellValue::Null, |value| CellValue::Str(value.to_string())));
+        }
+        column.sort_dictionary();
+        column
+    }
+
+    #[test]
+    fn strings_are_stored_once_and_grouped_by_code() {
+        let column = column(&[Some("US"), Some("UK"), None, Some("US"), Some("FR")]);
+        assert!(column.code(0) == column.code(3));
+        assert!(column.code(0) != column.code(1));
+        assert!(column.code(2) == CellCode(None));
+        assert!(column.code_of(&CellValue::Str("UK".to_string())) == column.code(1));
+        assert!(column.code_of(&CellValue::Str("DE".to_string())) == CellCode(None));
+        assert_eq!(column.value_of(column.code(4)).label(), "FR");
+        assert!(column.value_of(column.code(2)).is_null());
+    }
+
+    #[test]
+    fn codes_are_ordered_by_their_strings() {
+        let mut column = column(&[Some("US"), Some("UK"), None, Some("FR")]);
+        let order = |column: &Column| {
+            let mut rows: Vec<usize> = (0..column.len()).collect();
+            rows.sort_by(|a, b| column.compare_codes(column.code(*a), column.code(*b)));
+            rows
+        };
+        assert_eq!(order(&column), vec![2, 3, 1, 0]);
+        assert_eq!(column.compare(3, 1), Ordering::Less);
+
+        // strings which are added later are ranked among the others once the dictionary
+        // is sorted again
+        column.push(&CellValue::Str("IT".to_string()));
+        column.push(&CellValue::Str("AU".to_string()));
+        column.sort_dictionary();
+        assert_eq!(order(&column), vec![2, 5, 3, 4, 1, 0]);
+    }
+
+    #[test]
+    fn strings_are_filtered_by_code() {
+        let column = column(&[Some("US"), Some("UK"), None, Some("US"), Some("FR")]);
+        let filter = |operation: Operation, value: FilterValue| {
+            let mut rows: Vec<usize> = (0..column.len()).collect();
+            column.filter(&mut rows, &operation, &value);
+            // each row matches in the same way when it is checked on its own
+            let matching: Vec<usize> = (0..column.len())
+                .filter(|row| column.matches(*row, &operation, &value))
+                .collect();
+            assert_eq!(rows, matching);
+            rows
+        };
+        let us = || FilterValue::Value(CellValue::Str("US".to_string()));
+        assert_eq!(filter(Operation::EqEq, us()), vec![0, 3]);
+        assert_eq!(filter(Operation::NotEq, us()), vec![1, 4]);
+        assert_eq!(filter(Operation::Lt, us()), vec![1, 4]);
+        assert_eq!(filter(Operation::IsNull, us()), vec![2]);
+        let in_list = FilterValue::List(vec![
+            CellValue::Str("FR".to_string()),
+            CellValue::Str("UK".to_string()),
+        ]);
+        assert_eq!(filter(Operation::In, in_list), vec![1, 4]);
+    }
+
+    #[test]
+    fn strings_of_removed_rows_keep_their_codes() {
+        let mut column = column(&[Some("US"), Some("UK"), Some("FR")]);
+        let uk = column.code(1);
+        column.retain(&[true, false, true]);
+        assert_eq!(column.len(), 2);
+        assert!(column.code_of(&CellValue::Str("UK".to_string())) == uk);
+        assert_eq!(column.value_of(uk).label(), "UK");
+        assert_eq!(column.get(1).label(), "FR");
+    }
+}
@@ -1,20 +1,33 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use super::cell_value::CellValue;
+use super::error::Error;
+use super::schema::ColumnType;
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub sort: Vec<SortDescriptor>,
     pub columns: Vec<String>,
-    pub filter: Vec<FilterDescriptor>,
+    pub filter: Vec<Filter>,
+    // how the filters are combined, all of them must match unless given
+    #[serde(default)]
+    pub filter_op: FilterOp,
     pub aggregates: HashMap<String, Aggregate>,
     pub row_pivots: Vec<String>,
+    // also known as split_by, where each combination of their values has its own set
+    // of columns
+    #[serde(default, alias = "split_by")]
+    pub column_pivots: Vec<String>,
+    #[serde(default)]
+    pub having: Vec<HavingDescriptor>,
 }
 
 impl Config {
-    pub fn new(config_string: String) -> Config {
-        let config: Config = serde_json::from_str(config_string.as_str()).unwrap();
-        return config;
+    pub fn new(config_string: String) -> Result<Config, Error> {
+        serde_json::from_str(config_string.as_str())
+            .map_err(|error| Error::InvalidConfig(error.to_string()))
     }
 }
 
@@ -24,11 +37,74 @@ pub struct SortDescriptor {
     pub order: SortOrder,
 }
 
+// either a single filter, e.g. ["Country", "==", "US"], or a group of filters,
+// e.g. {"filter_op": "or", "filters": [["Country", "==", "US"], ["Country", "==", "UK"]]}
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Filter {
+    Descriptor(FilterDescriptor),
+    Group(FilterGroup),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FilterGroup {
+    #[serde(default)]
+    pub filter_op: FilterOp,
+    pub filters: Vec<Filter>,
+}
+
+// and - all of the filters match, or - any of them match, not - none of them match
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterOp {
+    #[default]
+    And,
+    Or,
+    Not,
+}
+
+// filters on the aggregated values of the groups at a depth of the pivot tree, where
+// depth 1 holds the groups of the first row pivot. Groups which don't match are hidden
+// along with their descendants, e.g. {"depth": 1, "filter": [["Gold", ">", 100]]}
+#[derive(Serialize, Deserialize)]
+pub struct HavingDescriptor {
+    pub depth: usize,
+    pub filter: Vec<Filter>,
+    #[serde(default)]
+    pub filter_op: FilterOp,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FilterDescriptor {
     pub column: String,
     pub operation: Operation,
-    pub value: CellValue,
+    // not needed for the null checks
+    #[serde(default)]
+    pub value: FilterValue,
+}
+
+// the value a column is compared against, which is a list for `in` and `not in`
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum FilterValue {
+    List(Vec<CellValue>),
+    Value(CellValue),
+}
+
+impl FilterValue {
+    // the values of a list, where a single value is treated as a list of one
+    pub fn values(&self) -> &[CellValue] {
+        match self {
+            FilterValue::List(values) => values,
+            FilterValue::Value(value) => std::slice::from_ref(value),
+        }
+    }
+}
+
+impl Default for FilterValue {
+    fn default() -> FilterValue {
+        FilterValue::Value(CellValue::Null)
+    }
 }
 
 impl Clone for SortDescriptor {
@@ -40,26 +116,252 @@ impl Clone for SortDescriptor {
     }
 }
 
+// the sort orders the Perspective viewer cycles through, where the "col" orders sort
+// the columns of column pivots by their totals rather than sorting the rows
 #[derive(Serialize, Deserialize, Copy, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum SortOrder {
     Desc,
     Asc,
     None,
+    #[serde(rename = "asc abs")]
+    AscAbs,
+    #[serde(rename = "desc abs")]
+    DescAbs,
+    #[serde(rename = "col asc")]
+    ColAsc,
+    #[serde(rename = "col desc")]
+    ColDesc,
+    #[serde(rename = "col asc abs")]
+    ColAscAbs,
+    #[serde(rename = "col desc abs")]
+    ColDescAbs,
+}
+
+// which way values are sorted, where abs sorts numbers by their magnitude
+#[derive(Copy, Clone)]
+pub struct SortDirection {
+    pub descending: bool,
+    pub abs: bool,
+}
+
+impl SortOrder {
+    // the direction the rows are sorted in, if they are sorted
+    pub fn row_direction(&self) -> Option<SortDirection> {
+        let (descending, abs) = match self {
+            SortOrder::Asc => (false, false),
+            SortOrder::Desc => (true, false),
+            SortOrder::AscAbs => (false, true),
+            SortOrder::DescAbs => (true, true),
+            _ => return None,
+        };
+        Some(SortDirection { descending, abs })
+    }
+
+    // the direction the columns of column pivots are sorted in, if they are sorted
+    pub fn column_direction(&self) -> Option<SortDirection> {
+        let (descending, abs) = match self {
+            SortOrder::ColAsc => (false, false),
+            SortOrder::ColDesc => (true, false),
+            SortOrder::ColAscAbs => (false, true),
+            SortOrder::ColDescAbs => (true, true),
+            _ => return None,
+        };
+        Some(SortDirection { descending, abs })
+    }
+}
+
+impl SortDirection {
+    pub fn apply(&self, order: Ordering) -> Ordering {
+        if self.descending {
+            order.reverse()
+        } else {
+            order
+        }
+    }
+
+    pub fn compare(&self, a: &CellValue, b: &CellValue) -> Ordering {
+        if self.abs {
+            self.apply(a.magnitude().cmp(&b.magnitude()))
+        } else {
+            self.apply(a.cmp(b))
+        }
+    }
 }
 
+// the filter operators, using the same names as Perspective's filters,
+// e.g. ["Country", "==", "US"] or ["Name", "begins with", "A"]
 #[derive(Serialize, Deserialize, Copy, Clone)]
-#[serde(rename_all = "lowercase")]
 pub enum Operation {
+    #[serde(rename = "==", alias = "eqeq")]
     EqEq,
+    #[serde(rename = "!=")]
+    NotEq,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    LtEq,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    GtEq,
+    #[serde(rename = "contains")]
+    Contains,
+    #[serde(rename = "begins with")]
+    BeginsWith,
+    #[serde(rename = "ends with")]
+    EndsWith,
+    #[serde(rename = "in")]
+    In,
+    #[serde(rename = "not in")]
+    NotIn,
+    #[serde(rename = "is null")]
+    IsNull,
+    #[serde(rename = "is not null")]
+    IsNotNull,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
-#[serde(rename_all = "camelCase")]
+// the aggregates, using the same names as Perspective, where those which depend on
+// a second column name it alongside, e.g. "sum" or ["weighted mean", "Population"],
+// and join can be given the maximum number of values it shows, e.g. ["join", 5]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "AggregateName", into = "AggregateName")]
 pub enum Aggregate {
     Sum,
     Count,
     Low,
     High,
+    Avg,
+    WeightedMean(String),
+    Median,
+    DistinctCount,
+    Unique,
+    Dominant,
+    First,
+    Last,
+    LastByIndex,
+    StdDevPop,
+    StdDevSample,
+    VarPop,
+    VarSample,
+    PctSumParent,
+    PctSumGrandTotal,
+    Join(usize),
+    Any,
+    And,
+    Or,
     Undefined,
 }
+
+pub const DEFAULT_JOIN_LIMIT: usize = 10;
+
+impl Aggregate {
+    // whether the aggregate can be applied to a column of the given type, where the
+    // arithmetic aggregates need numbers and the logical ones need booleans
+    pub fn accepts(&self, column_type: ColumnType) -> bool {
+        match self {
+            Aggregate::Sum
+            | Aggregate::Avg
+            | Aggregate::WeightedMean(_)
+            | Aggregate::Median
+            | Aggregate::StdDevPop
+            | Aggregate::StdDevSample
+            | Aggregate::VarPop
+            | Aggregate::VarSample
+            | Aggregate::PctSumParent
+            | Aggregate::PctSumGrandTotal => {
+                matches!(column_type, ColumnType::Integer | ColumnType::Float)
+            }
+            Aggregate::Low | Aggregate::High => {
+                !matches!(column_type, ColumnType::String | ColumnType::Boolean)
+            }
+            Aggregate::And | Aggregate::Or => column_type == ColumnType::Boolean,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AggregateName {
+    Name(String),
+    WithColumn(String, String),
+    WithLimit(String, usize),
+}
+
+impl TryFrom<AggregateName> for Aggregate {
+    type Error = String;
+
+    fn try_from(name: AggregateName) -> Result<Aggregate, String> {
+        match name {
+            AggregateName::Name(name) => match name.as_str() {
+                "sum" => Ok(Aggregate::Sum),
+                "count" => Ok(Aggregate::Count),
+                "low" => Ok(Aggregate::Low),
+                "high" => Ok(Aggregate::High),
+                "avg" | "mean" => Ok(Aggregate::Avg),
+                "median" => Ok(Aggregate::Median),
+                "distinct count" => Ok(Aggregate::DistinctCount),
+                "unique" => Ok(Aggregate::Unique),
+                "dominant" => Ok(Aggregate::Dominant),
+                "first" => Ok(Aggregate::First),
+                "last" => Ok(Aggregate::Last),
+                "last by index" => Ok(Aggregate::LastByIndex),
+                "stddev" | "stddev pop" => Ok(Aggregate::StdDevPop),
+                "stddev sample" => Ok(Aggregate::StdDevSample),
+                "var" | "var pop" => Ok(Aggregate::VarPop),
+                "var sample" => Ok(Aggregate::VarSample),
+                "pct sum parent" => Ok(Aggregate::PctSumParent),
+                "pct sum grand total" => Ok(Aggregate::PctSumGrandTotal),
+                "join" => Ok(Aggregate::Join(DEFAULT_JOIN_LIMIT)),
+                "any" => Ok(Aggregate::Any),
+                "and" => Ok(Aggregate::And),
+                "or" => Ok(Aggregate::Or),
+                "undefined" => Ok(Aggregate::Undefined),
+                _ => Err(format!("unknown aggregate '{}'", name)),
+            },
+            AggregateName::WithColumn(name, column) => match name.as_str() {
+                "weighted mean" => Ok(Aggregate::WeightedMean(column)),
+                _ => Err(format!("unknown aggregate '{}'", name)),
+            },
+            AggregateName::WithLimit(name, limit) => match name.as_str() {
+                "join" => Ok(Aggregate::Join(limit)),
+                _ => Err(format!("unknown aggregate '{}'", name)),
+            },
+        }
+    }
+}
+
+impl From<Aggregate> for AggregateName {
+    fn from(aggregate: Aggregate) -> AggregateName {
+        let name = match aggregate {
+            Aggregate::Sum => "sum",
+            Aggregate::Count => "count",
+            Aggregate::Low => "low",
+            Aggregate::High => "high",
+            Aggregate::Avg => "avg",
+            Aggregate::WeightedMean(column) => {
+                return AggregateName::WithColumn("weighted mean".to_string(), column)
+            }
+            Aggregate::Median => "median",
+            Aggregate::DistinctCount => "distinct count",
+            Aggregate::Unique => "unique",
+            Aggregate::Dominant => "dominant",
+            Aggregate::First => "first",
+            Aggregate::Last => "last",
+            Aggregate::LastByIndex => "last by index",
+            Aggregate::StdDevPop => "stddev pop",
+            Aggregate::StdDevSample => "stddev sample",
+            Aggregate::VarPop => "var pop",
+            Aggregate::VarSample => "var sample",
+            Aggregate::PctSumParent => "pct sum parent",
+            Aggregate::PctSumGrandTotal => "pct sum grand total",
+            Aggregate::Join(limit) => return AggregateName::WithLimit("join".to_string(), limit),
+            Aggregate::Any => "any",
+            Aggregate::And => "and",
+            Aggregate::Or => "or",
+            Aggregate::Undefined => "undefined",
+        };
+        AggregateName::Name(name.to_string())
+    }
+}
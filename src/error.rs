@@ -0,0 +1,36 @@
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+// the errors which are returned to callers rather than panicking, so that bad input
+// can be reported without bringing down the wasm module
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // a column which isn't in the table, e.g. one named by a view config
+    UnknownColumn(String),
+    // a config, options or schema object which couldn't be parsed
+    InvalidConfig(String),
+    // values which don't match the types expected of them, e.g. rows which aren't objects
+    // or values which don't match the types of their columns
+    TypeMismatch(String),
+    // input which has nothing in it, e.g. a table created from an empty array
+    EmptyInput(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownColumn(column) => write!(f, "Unknown column '{}'", column),
+            Error::InvalidConfig(message) => write!(f, "Invalid config: {}", message),
+            Error::TypeMismatch(message) => write!(f, "Type mismatch: {}", message),
+            Error::EmptyInput(message) => write!(f, "Empty input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for JsValue {
+    fn from(error: Error) -> JsValue {
+        JsValue::from(error.to_string().as_str())
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use super::cell_value::CellValue;
+use super::column::Column;
+use super::config::{Filter, FilterOp, FilterValue, Operation};
+use super::error::Error;
+use super::table::Table;
+
+// the filters of a view, with their columns resolved to column indices. Groups
+// without any filters match every row, whichever way they are combined.
+pub enum FilterExpr {
+    Descriptor {
+        column_index: usize,
+        operation: Operation,
+        value: FilterValue,
+    },
+    Group {
+        filter_op: FilterOp,
+        filters: Vec<FilterExpr>,
+    },
+}
+
+impl FilterExpr {
+    pub fn new(
+        table: &Table,
+        filters: &[Filter],
+        filter_op: FilterOp,
+    ) -> Result<FilterExpr, Error> {
+        Ok(FilterExpr::Group {
+            filter_op,
+            filters: filters
+                .iter()
+                .map(|filter| match filter {
+                    Filter::Descriptor(desc) => Ok(FilterExpr::Descriptor {
+                        column_index: table.index_for_column(&desc.column)?,
+                        operation: desc.operation,
+                        value: desc.value.clone(),
+                    }),
+                    Filter::Group(group) => FilterExpr::new(table, &group.filters, group.filter_op),
+                })
+                .collect::<Result<Vec<FilterExpr>, Error>>()?,
+        })
+    }
+
+    // a group which matches when all of the given filters do
+    pub fn all(filters: Vec<FilterExpr>) -> FilterExpr {
+        FilterExpr::Group {
+            filter_op: FilterOp::And,
+            filters,
+        }
+    }
+
+    // whether a row, given by its values, satisfies the filters
+    pub fn matches(&self, row: &[CellValue]) -> bool {
+        self.evaluate(&|column_index, operation, value| row[column_index].matches(operation, value))
+    }
+
+    // as above, for a row which is read from the columns of the table
+    pub fn matches_row(&self, data: &[Column], row_index: usize) -> bool {
+        self.evaluate(&|column_index, operation, value| {
+            data[column_index].matches(row_index, operation, value)
+        })
+    }
+
+    fn evaluate(&self, matches: &dyn Fn(usize, &Operation, &FilterValue) -> bool) -> bool {
+        match self {
+            FilterExpr::Descriptor {
+                column_index,
+                operation,
+                value,
+            } => matches(*column_index, operation, value),
+            FilterExpr::Group { filters, .. } if filters.is_empty() => true,
+            FilterExpr::Group { filter_op, filters } => {
+                let mut results = filters.iter().map(|filter| filter.evaluate(matches));
+                match filter_op {
+                    FilterOp::And => results.all(|result| result),
+                    FilterOp::Or => results.any(|result| result),
+                    FilterOp::Not => !results.any(|result| result),
+                }
+            }
+        }
+    }
+
+    // removes the rows which don't satisfy the filters, a column at a time
+    pub fn filter(&self, data: &[Column], rows: &mut Vec<usize>) {
+        match self {
+            FilterExpr::Descriptor {
+                column_index,
+                operation,
+                value,
+            } => data[*column_index].filter(rows, operation, value),
+            FilterExpr::Group { filters, .. } if filters.is_empty() => {}
+            FilterExpr::Group {
+                filter_op: FilterOp::And,
+                filters,
+            } => {
+                for filter in filters {
+                    filter.filter(data, rows);
+                }
+            }
+            FilterExpr::Group { filter_op, filters } => {
+                // the rows which match any of the filters
+                let mut matched: HashSet<usize> = HashSet::new();
+                for filter in filters {
+                    let mut filtered = rows.clone();
+                    filter.filter(data, &mut filtered);
+                    matched.extend(filtered);
+                }
+                let negate = matches!(filter_op, FilterOp::Not);
+                rows.retain(|row_index| matched.contains(row_index) != negate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::schema::ColumnType;
+    use serde_json::{json, Value};
+
+    fn table() -> Table {
+        let schema = vec![
+            ("id".to_string(), ColumnType::Integer),
+            ("country".to_string(), ColumnType::String),
+            ("medals".to_string(), ColumnType::Integer),
+        ];
+        let mut table = Table::with_schema(schema, Some("id".to_string())).unwrap();
+        table
+            .update_rows(&[
+                json!({"id": 0, "country": "US", "medals": 5}),
+                json!({"id": 1, "country": "UK", "medals": 3}),
+                json!({"id": 2, "country": "FR", "medals": 7}),
+                json!({"id": 3, "country": "US", "medals": 1}),
+                json!({"id": 4, "medals": 2}),
+            ])
+            .unwrap();
+        table
+    }
+
+    // the ids of the rows which satisfy the filters of a config, checking that
+    // filtering a column at a time agrees with matching each row on its own
+    fn ids(table: &Table, filter: Value, filter_op: Value) -> Vec<usize> {
+        let config = json!({
+            "columns": ["id"],
+            "row_pivots": [],
+            "aggregates": {},
+            "sort": [],
+            "filter": filter,
+            "filter_op": filter_op,
+        });
+        let config = Config::new(config.to_string()).unwrap();
+        let filter = FilterExpr::new(table, &config.filter, config.filter_op).unwrap();
+        let data = table.data.borrow();
+        let mut rows = table.row_indices();
+        filter.filter(&data, &mut rows);
+        for row_index in table.row_indices() {
+            assert_eq!(
+                filter.matches_row(&data, row_index),
+                rows.contains(&row_index)
+            );
+            assert_eq!(
+                filter.matches(&table.row_values(row_index)),
+                rows.contains(&row_index)
+            );
+        }
+        rows
+    }
+
+    #[test]
+    fn flat_lists_of_filters_must_all_match_unless_combined_otherwise() {
+        let table = table();
+        let filters = json!([["country", "==", "US"], ["medals", ">", 2]]);
+        assert_eq!(ids(&table, filters.clone(), json!("and")), vec![0]);
+        assert_eq!(ids(&table, filters.clone(), json!("or")), vec![0, 1, 2, 3]);
+        assert_eq!(ids(&table, filters, json!("not")), vec![4]);
+        // without any filters every row matches
+        assert_eq!(ids(&table, json!([]), json!("or")), vec![0, 1, 2, 3, 4]);
+        assert_eq!(ids(&table, json!([]), json!("not")), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn groups_of_filters_can_be_nested() {
+        let table = table();
+        // (country == US or country == UK) and not (medals < 2 or country is null)
+        let filters = json!([
+            {
+                "filter_op": "or",
+                "filters": [["country", "==", "US"], ["country", "==", "UK"]],
+            },
+            {
+                "filter_op": "not",
+                "filters": [["medals", "<", 2], ["country", "is null"]],
+            },
+        ]);
+        assert_eq!(ids(&table, filters, json!("and")), vec![0, 1]);
+
+        // groups are combined with and by default
+        let filters = json!([{"filters": [["country", "==", "US"], ["medals", ">=", 5]]}]);
+        assert_eq!(ids(&table, filters, json!("or")), vec![0]);
+    }
+
+    #[test]
+    fn filters_on_unknown_columns_are_errors() {
+        let table = table();
+        let config = Config::new(
+            json!({
+                "columns": ["id"],
+                "row_pivots": [],
+                "aggregates": {},
+                "sort": [],
+                "filter": [{"filter_op": "or", "filters": [["year", "==", 2008]]}],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let error = FilterExpr::new(&table, &config.filter, config.filter_op).err();
+        assert_eq!(error, Some(Error::UnknownColumn("year".to_string())));
+    }
+}
@@ -1,13 +1,27 @@
 mod accumulator;
 mod cell_value;
+mod column;
 mod config;
+mod error;
+mod filter;
 mod macros;
 mod pivot_table;
 mod row_aggregator;
+mod schema;
 mod table;
+mod temporal;
 mod utils;
+mod validation;
 mod view;
 
+// the api for use from rust, alongside the wasm bindings
+pub use error::Error;
+pub use pivot_table::{SerializableColumnarPivotTable, SerializablePivotTable};
+pub use schema::ColumnType;
+pub use table::Table;
+pub use validation::ConfigProblem;
+pub use view::{UpdateMode, View, ViewUpdate};
+
 #[macro_use]
 extern crate serde_derive;
 
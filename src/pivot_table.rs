@@ -1,31 +1,88 @@
 use itertools::Itertools;
-use std::cmp::Eq;
+use std::cell::{Ref, RefCell};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-use super::accumulator::Accumulator;
+use super::accumulator::{percentage, Accumulator};
 use super::cell_value::CellValue;
-use super::config::{SortDescriptor, SortOrder};
+use super::column::{CellCode, Column};
+use super::config::{HavingDescriptor, SortDescriptor, SortDirection, SortOrder};
+use super::error::Error;
+use super::filter::FilterExpr;
 use super::row_aggregator::RowAggregator;
 use super::table::Table;
 use super::view::ViewOptions;
 
 pub struct PivotTable {
-    rows: Vec<PivotTableRow>,
+    // when there are no pivots, the indices of the table rows in sorted order
+    sorted_rows: Vec<usize>,
+    // when there are pivots, a tree with a level of aggregates for each pivot
+    root: PivotNode,
+    row_pivot_indices: Vec<usize>,
+    // the order of the groups at each level of the tree
+    pivot_orders: Vec<SortOrder>,
+    // the groups with paths up to this length are expanded, unless they have been
+    // expanded or collapsed individually
+    depth: usize,
+    sort: Vec<IndexedSortDescriptor>,
+    aggregation: Aggregation,
+    // when there are pivots, the paths of the groups in the order they are displayed,
+    // which is only worked out again after the tree or its expanded groups change
+    display_order: RefCell<Option<Vec<Vec<CellCode>>>>,
     // TODO - (biggish refactor) we shouldn't need this, instead the rows above should only contain the columns
     // requested by the view config.
     columns: Vec<String>,
 }
 
-// represents an aggregate over a collection of rows, each sharing the same key
-struct PivotTableRow {
+// describes how the groups of the pivot tree are aggregated
+struct Aggregation {
+    accumulators: Vec<Accumulator>,
+    column_pivot_indices: Vec<usize>,
+    // filters on the aggregated values at each depth of the tree
+    having: Vec<Option<FilterExpr>>,
+}
+
+// represents an aggregate over a collection of rows, each sharing the same values for
+// the pivot columns, where the values are held as codes until they are serialized
+struct PivotNode {
+    values: Vec<CellValue>,
+    // the state the values are computed from, which is merged into the parent's
+    aggregator: RowAggregator,
+    // for groups with children, the aggregate of all of their rows, including those of
+    // hidden children, which the percentages of the groups below are shares of
+    total: RowAggregator,
+    total_splits: HashMap<Vec<CellCode>, RowAggregator>,
+    path: Vec<CellCode>,
+    // keyed by the value of the pivot column at the next level down
+    children: HashMap<CellCode, PivotNode>,
+    // for the leaf nodes, the indices of the table rows which are aggregated
+    rows: Vec<usize>,
+    // groups which don't match the having filters are hidden, rather than removed, so
+    // that they can reappear as their rows change
+    hidden: bool,
+    // when there are column pivots, the aggregates of the rows which share each
+    // combination of their values
+    splits: HashMap<Vec<CellCode>, Split>,
+    // whether the group has been expanded or collapsed, rather than following the
+    // depth of the pivot table
+    expanded: Option<bool>,
+    // the children which are shown, in the order they are displayed, which is patched
+    // as they change rather than sorted again
+    order: Vec<CellCode>,
+}
+
+// the aggregate of the rows of a group which share the same values for the column pivots
+struct Split {
+    aggregator: RowAggregator,
     values: Vec<CellValue>,
-    key: RowKey,
 }
 
-struct IndexedPivotTableRow {
-    row: PivotTableRow,
+// a column of the output, which for views with column pivots only holds the values of
+// the rows sharing the values of its split, and is named after them, e.g. 2008|Gold|Medals
+struct OutputColumn {
+    name: String,
     index: usize,
+    split: Option<Vec<CellCode>>,
 }
 
 #[derive(Clone, Serialize)]
@@ -35,68 +92,540 @@ struct RowKey {
 
 // a format which is appropriate for serializing to the client
 #[derive(Serialize)]
-pub struct SerializableColumnarPivotTable<'a> {
-    columns: HashMap<String, Vec<&'a CellValue>>,
+pub struct SerializableColumnarPivotTable {
+    columns: HashMap<String, Vec<CellValue>>,
     row_paths: Vec<RowKey>,
 }
 
 #[derive(Serialize)]
-pub struct SerializablePivotTable<'a> {
-    rows: Vec<HashMap<String, &'a CellValue>>,
+pub struct SerializablePivotTable {
+    rows: Vec<HashMap<String, CellValue>>,
     row_paths: Vec<RowKey>,
 }
 
+// a row of the output, which is either a row of the table or the aggregate of a group
+enum OutputRow<'a> {
+    Row(usize),
+    Group(&'a PivotNode),
+}
+
+// the rows of a pivot table which were added or changed by an update
+pub enum UpdatedRows {
+    // the indices of the table rows, when there are no pivots
+    Rows(Vec<usize>),
+    // the paths of the groups which were re-aggregated
+    Groups(HashSet<Vec<CellCode>>),
+}
+
 impl RowKey {
-    fn new(row: &Vec<CellValue>, indices: &Vec<usize>) -> RowKey {
+    // the values of the pivot columns for the given path
+    fn new(data: &[Column], indices: &[usize], path: &[CellCode]) -> RowKey {
         RowKey {
-            values: indices.iter().map(|i| row[*i].clone()).collect(),
+            values: indices
+                .iter()
+                .zip(path.iter())
+                .map(|(i, code)| data[*i].value_of(*code))
+                .collect(),
+        }
+    }
+}
+
+impl PivotNode {
+    fn new(path: Vec<CellCode>) -> PivotNode {
+        PivotNode {
+            values: vec![],
+            aggregator: RowAggregator::default(),
+            total: RowAggregator::default(),
+            total_splits: HashMap::new(),
+            path,
+            children: HashMap::new(),
+            rows: vec![],
+            hidden: false,
+            splits: HashMap::new(),
+            expanded: None,
+            order: vec![],
         }
     }
 
-    fn empty() -> RowKey {
-        RowKey { values: vec![] }
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty() && self.children.is_empty()
     }
 
-    fn depth(&self) -> usize {
-        self.values.len()
+    fn get_node(&self, path: &[CellCode]) -> Option<&PivotNode> {
+        let mut node = self;
+        for code in path {
+            node = node.children.get(code)?;
+        }
+        Some(node)
     }
 
-    fn eq_depth(&self, other: &Self, depth: &usize) -> bool {
-        if self.values.len() != other.values.len() {
-            return false;
+    fn find_node(&mut self, path: &[CellCode]) -> Option<&mut PivotNode> {
+        let mut node = self;
+        for code in path {
+            node = node.children.get_mut(code)?;
+        }
+        Some(node)
+    }
+
+    fn renumber_rows(&mut self, row_indices: &[usize]) {
+        for row_index in self.rows.iter_mut() {
+            *row_index = row_indices[*row_index];
+        }
+        for child in self.children.values_mut() {
+            child.renumber_rows(row_indices);
+        }
+    }
+
+    // finds the leaf for the given path, creating it (and its parents) if needed
+    fn find_or_insert_leaf(&mut self, path: &[CellCode]) -> &mut PivotNode {
+        let mut node = self;
+        for (depth, code) in path.iter().enumerate() {
+            node = node
+                .children
+                .entry(*code)
+                .or_insert_with(|| PivotNode::new(path[..=depth].to_vec()));
+        }
+        node
+    }
+
+    // re-aggregates the totals of the nodes along the given (sorted) leaf paths, from
+    // the bottom up, removing any which no longer contain any rows
+    fn refresh(
+        &mut self,
+        paths: &[Vec<CellCode>],
+        data: &[Column],
+        row_pivot_indices: &[usize],
+        aggregation: &Aggregation,
+    ) {
+        let depth = self.path.len();
+        if paths.iter().any(|path| path.len() == depth) {
+            // this is a leaf, which aggregates the table rows directly
+            if !self.rows.is_empty() {
+                self.aggregator =
+                    RowAggregator::from_columns(data, &self.rows, &aggregation.accumulators);
+                self.splits = aggregation.split_rows(data, &self.rows);
+            }
+            return;
+        }
+
+        for (code, paths) in group_paths(paths, depth) {
+            let is_empty = match self.children.get_mut(code) {
+                Some(child) => {
+                    child.refresh(paths, data, row_pivot_indices, aggregation);
+                    child.is_empty()
+                }
+                None => false,
+            };
+            if is_empty {
+                self.children.remove(code);
+            }
         }
-        if self.values.len() == 0 {
-            // empty row keys are always considered unequal
+
+        if !self.children.is_empty() {
+            let children = self.sorted_children(&data[row_pivot_indices[depth]]);
+            let totals: Vec<&RowAggregator> = children.iter().map(|child| child.total()).collect();
+            let total = RowAggregator::merge(&totals);
+            let total_splits = merge_total_splits(&children);
+            self.total = total;
+            self.total_splits = total_splits;
+        }
+    }
+
+    // works out the values of the nodes along the given leaf paths, from the bottom up,
+    // along with whether they match the having filters, collecting the paths of the
+    // groups which change. This follows `refresh`, as the percentages are shares of the
+    // totals of the parent and of the root, which include the groups that are hidden.
+    // The other groups are only visited when their shares may have changed, and are
+    // only re-aggregated if some of their children are shown or hidden. Returns whether
+    // the values this group adds to its parent's have changed.
+    fn show(
+        &mut self,
+        paths: &[Vec<CellCode>],
+        data: &[Column],
+        row_pivot_indices: &[usize],
+        aggregation: &Aggregation,
+        shares: Option<Shares>,
+        changed: &mut HashSet<Vec<CellCode>>,
+    ) -> bool {
+        let depth = self.path.len();
+        let was_hidden = self.hidden;
+        let mut merge = !paths.is_empty();
+        if self.is_empty() {
             return false;
         }
-        let items: Vec<_> = self
-            .values
+        if !self.children.is_empty() {
+            // the root is its own parent and grand total
+            let grand_total = shares.map_or((&self.total, &self.total_splits), |shares| {
+                (shares.grand_total, shares.grand_total_splits)
+            });
+            let child_shares = Shares {
+                parent: &self.total,
+                parent_splits: &self.total_splits,
+                grand_total: grand_total.0,
+                grand_total_splits: grand_total.1,
+            };
+            if aggregation.shares_change(merge) {
+                let paths: HashMap<&CellCode, &[Vec<CellCode>]> =
+                    group_paths(paths, depth).collect();
+                for (code, child) in self.children.iter_mut() {
+                    merge |= child.show(
+                        paths.get(code).copied().unwrap_or_default(),
+                        data,
+                        row_pivot_indices,
+                        aggregation,
+                        Some(child_shares),
+                        changed,
+                    );
+                }
+            } else {
+                for (code, paths) in group_paths(paths, depth) {
+                    if let Some(child) = self.children.get_mut(code) {
+                        merge |= child.show(
+                            paths,
+                            data,
+                            row_pivot_indices,
+                            aggregation,
+                            Some(child_shares),
+                            changed,
+                        );
+                    }
+                }
+            }
+
+            // the values only include the children which are shown, and a group is
+            // hidden if all of its children are, when it has no values or splits of
+            // its own
+            if merge {
+                let children = self.visible_children(&data[row_pivot_indices[depth]]);
+                if children.is_empty() {
+                    if !self.hidden {
+                        changed.insert(self.path.clone());
+                    }
+                    self.aggregator = RowAggregator::default();
+                    self.values.clear();
+                    self.splits.clear();
+                    self.hidden = true;
+                    return !was_hidden;
+                }
+                let aggregators: Vec<&RowAggregator> =
+                    children.iter().map(|child| &child.aggregator).collect();
+                let aggregator = RowAggregator::merge(&aggregators);
+                let splits = merge_splits(&children);
+                self.aggregator = aggregator;
+                self.splits = splits;
+            } else if self.values.is_empty() {
+                // the children are all still hidden, so there are no values to work out
+                return false;
+            }
+        }
+
+        let shares = shares.unwrap_or(Shares {
+            parent: &self.total,
+            parent_splits: &self.total_splits,
+            grand_total: &self.total,
+            grand_total_splits: &self.total_splits,
+        });
+        let accumulators = &aggregation.accumulators;
+        // groups which weren't re-aggregated only need their shares working out again
+        let mut values = if merge {
+            self.aggregator.to_row()
+        } else {
+            self.values.clone()
+        };
+        replace_percentages(
+            &mut values,
+            &self.aggregator,
+            shares.parent,
+            shares.grand_total,
+            accumulators,
+        );
+        // each split is a share of the same split of the parent
+        let mut splits_changed = false;
+        for (path, split) in self.splits.iter_mut() {
+            let mut split_values = if merge {
+                split.aggregator.to_row()
+            } else {
+                split.values.clone()
+            };
+            if let (Some(parent), Some(grand_total)) = (
+                shares.parent_splits.get(path),
+                shares.grand_total_splits.get(path),
+            ) {
+                replace_percentages(
+                    &mut split_values,
+                    &split.aggregator,
+                    parent,
+                    grand_total,
+                    accumulators,
+                );
+            }
+            // values of different types can compare as equal, e.g. 1 and 1.0
+            splits_changed |= split.values != split_values;
+            split.values = split_values;
+        }
+
+        let hidden = !aggregation.includes(depth, &values);
+        if splits_changed || hidden != self.hidden || values != self.values {
+            changed.insert(self.path.clone());
+        }
+        self.values = values;
+        self.hidden = hidden;
+        hidden != was_hidden || (merge && !hidden)
+    }
+
+    // the aggregate of all of the rows of the group, whether or not they are shown
+    fn total(&self) -> &RowAggregator {
+        if self.children.is_empty() {
+            &self.aggregator
+        } else {
+            &self.total
+        }
+    }
+
+    // the aggregates of all of the rows of the group which share each combination of
+    // values of the column pivots
+    fn total_splits(&self) -> Vec<(&Vec<CellCode>, &RowAggregator)> {
+        if self.children.is_empty() {
+            self.splits
+                .iter()
+                .map(|(path, split)| (path, &split.aggregator))
+                .collect()
+        } else {
+            self.total_splits.iter().collect()
+        }
+    }
+
+    // all of the children, in ascending order of the value of the pivot column they share
+    fn sorted_children(&self, column: &Column) -> Vec<&PivotNode> {
+        let mut children: Vec<(&CellCode, &PivotNode)> = self.children.iter().collect();
+        children.sort_by(|(a, _), (b, _)| column.compare_codes(**a, **b));
+        children.into_iter().map(|(_, child)| child).collect()
+    }
+
+    // the children which aren't hidden, in ascending order of the value of the pivot
+    // column they share
+    fn visible_children(&self, column: &Column) -> Vec<&PivotNode> {
+        let mut children = self.sorted_children(column);
+        children.retain(|child| !child.hidden);
+        children
+    }
+
+    fn is_expanded(&self, depth: usize) -> bool {
+        !self.children.is_empty() && self.expanded.unwrap_or(self.path.len() <= depth)
+    }
+
+    // forgets which groups have been expanded or collapsed
+    fn reset_expanded(&mut self) {
+        self.expanded = None;
+        for child in self.children.values_mut() {
+            child.reset_expanded();
+        }
+    }
+
+    // patches the order of the children after the given children have changed, in the
+    // same way as the rows of a view without pivots, where the children are ordered by
+    // their aggregated values for the sorted columns, then by the value of the pivot
+    // column they share
+    fn update_order(
+        &mut self,
+        changed: &[CellCode],
+        data: &[Column],
+        row_pivot_indices: &[usize],
+        pivot_orders: &[SortOrder],
+        sort: &[IndexedSortDescriptor],
+    ) {
+        let depth = self.path.len();
+        let column = &data[row_pivot_indices[depth]];
+        let descending = matches!(pivot_orders[depth], SortOrder::Desc | SortOrder::DescAbs);
+        let children = &self.children;
+        let compare = |a: &CellCode, b: &CellCode| {
+            let by_key = column.compare_codes(*a, *b);
+            let by_key = if descending { by_key.reverse() } else { by_key };
+            compare_groups(&children[a], &children[b], sort, row_pivot_indices).then(by_key)
+        };
+
+        let mut added: Vec<CellCode> = changed
             .iter()
-            .take(*depth)
-            .zip(other.values.iter())
-            .filter(|(a, b)| a != b)
+            .cloned()
+            .filter(|code| children.get(code).is_some_and(|child| !child.hidden))
+            .collect();
+        added.sort_by(&compare);
+
+        let changed: HashSet<&CellCode> = changed.iter().collect();
+        self.order = self
+            .order
+            .iter()
+            .cloned()
+            .filter(|code| !changed.contains(code))
+            .merge_by(added, |a, b| compare(a, b) != Ordering::Greater)
             .collect();
-        return items.len() == 0;
     }
 
-    fn clone_depth(&self, depth: &usize) -> RowKey {
-        RowKey {
-            values: self.values.iter().take(*depth).map(|x| x.clone()).collect(),
+    // appends the paths of the descendants of this node in the order they are displayed,
+    // where those of collapsed groups are left out
+    fn flatten(&self, expanded_depth: usize, paths: &mut Vec<Vec<CellCode>>) {
+        for code in &self.order {
+            let child = &self.children[code];
+            paths.push(child.path.clone());
+            if child.is_expanded(expanded_depth) {
+                child.flatten(expanded_depth, paths);
+            }
         }
     }
 }
 
-impl PartialEq for RowKey {
-    fn eq(&self, other: &Self) -> bool {
-        self.eq_depth(other, &self.values.len())
+// the totals which the percentages of a group are shares of
+#[derive(Clone, Copy)]
+struct Shares<'a> {
+    parent: &'a RowAggregator,
+    parent_splits: &'a HashMap<Vec<CellCode>, RowAggregator>,
+    grand_total: &'a RowAggregator,
+    grand_total_splits: &'a HashMap<Vec<CellCode>, RowAggregator>,
+}
+
+// replaces the sums of the percentage columns with their share of the parent's sum, or
+// of the grand total
+fn replace_percentages(
+    values: &mut [CellValue],
+    aggregator: &RowAggregator,
+    parent: &RowAggregator,
+    grand_total: &RowAggregator,
+    accumulators: &[Accumulator],
+) {
+    for (column, accumulator) in accumulators.iter().enumerate() {
+        let total = match accumulator {
+            Accumulator::PctSumParent => parent,
+            Accumulator::PctSumGrandTotal => grand_total,
+            _ => continue,
+        };
+        values[column] = percentage(&aggregator.value(column), &total.value(column));
     }
 }
 
-impl Eq for RowKey {}
+// splits (sorted) paths into those which share each value at the given depth
+fn group_paths(
+    paths: &[Vec<CellCode>],
+    depth: usize,
+) -> impl Iterator<Item = (&CellCode, &[Vec<CellCode>])> {
+    paths
+        .chunk_by(move |a, b| a[depth] == b[depth])
+        .map(move |paths| (&paths[0][depth], paths))
+}
 
-struct KeyedRow<'a> {
-    values: &'a Vec<CellValue>,
-    key: RowKey,
+// merges the totals of the splits of the given groups
+fn merge_total_splits(nodes: &[&PivotNode]) -> HashMap<Vec<CellCode>, RowAggregator> {
+    let mut splits: HashMap<&Vec<CellCode>, Vec<&RowAggregator>> = HashMap::new();
+    for node in nodes {
+        for (path, aggregator) in node.total_splits() {
+            splits.entry(path).or_default().push(aggregator);
+        }
+    }
+    splits
+        .into_iter()
+        .map(|(path, aggregators)| (path.clone(), RowAggregator::merge(&aggregators)))
+        .collect()
+}
+
+// merges the splits of the given groups
+fn merge_splits(nodes: &[&PivotNode]) -> HashMap<Vec<CellCode>, Split> {
+    let paths: HashSet<&Vec<CellCode>> = nodes.iter().flat_map(|node| node.splits.keys()).collect();
+    paths
+        .into_iter()
+        .map(|path| {
+            let aggregators: Vec<&RowAggregator> = nodes
+                .iter()
+                .filter_map(|node| node.splits.get(path))
+                .map(|split| &split.aggregator)
+                .collect();
+            (path.clone(), Split::new(RowAggregator::merge(&aggregators)))
+        })
+        .collect()
+}
+
+impl Split {
+    fn new(aggregator: RowAggregator) -> Split {
+        let values = aggregator.to_row();
+        Split { aggregator, values }
+    }
+}
+
+impl Aggregation {
+    // the values of the column pivots for a row
+    fn split_path(&self, data: &[Column], row_index: usize) -> Vec<CellCode> {
+        self.column_pivot_indices
+            .iter()
+            .map(|i| data[*i].code(row_index))
+            .collect()
+    }
+
+    // aggregates the rows which share each combination of values of the column pivots
+    fn split_rows(&self, data: &[Column], rows: &[usize]) -> HashMap<Vec<CellCode>, Split> {
+        let mut groups: HashMap<Vec<CellCode>, Vec<usize>> = HashMap::new();
+        if !self.column_pivot_indices.is_empty() {
+            for row_index in rows {
+                groups
+                    .entry(self.split_path(data, *row_index))
+                    .or_default()
+                    .push(*row_index);
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(path, rows)| {
+                let aggregator = RowAggregator::from_columns(data, &rows, &self.accumulators);
+                (path, Split::new(aggregator))
+            })
+            .collect()
+    }
+
+    // whether the percentages of the groups which weren't re-aggregated can change, as
+    // they are shares of the grand total, or of a parent which was re-aggregated
+    fn shares_change(&self, parent_changed: bool) -> bool {
+        self.accumulators.iter().any(|acc| match acc {
+            Accumulator::PctSumGrandTotal => true,
+            Accumulator::PctSumParent => parent_changed,
+            _ => false,
+        })
+    }
+
+    // whether the aggregated values of a group at the given depth match the having filters
+    fn includes(&self, depth: usize, values: &[CellValue]) -> bool {
+        match self.having.get(depth) {
+            Some(Some(filter)) => filter.matches(values),
+            _ => true,
+        }
+    }
+}
+
+impl OutputRow<'_> {
+    fn value(&self, data: &[Column], column_index: usize) -> CellValue {
+        match self {
+            OutputRow::Row(row_index) => data[column_index].get(*row_index),
+            OutputRow::Group(node) => node.values[column_index].clone(),
+        }
+    }
+
+    fn column_value(
+        &self,
+        data: &[Column],
+        aggregation: &Aggregation,
+        column: &OutputColumn,
+    ) -> CellValue {
+        match (&column.split, self) {
+            (None, _) => self.value(data, column.index),
+            (Some(split), OutputRow::Row(row_index)) => {
+                if aggregation.split_path(data, *row_index).eq(split) {
+                    data[column.index].get(*row_index)
+                } else {
+                    CellValue::Null
+                }
+            }
+            (Some(split), OutputRow::Group(node)) => node
+                .splits
+                .get(split)
+                .map_or(CellValue::Null, |split| split.values[column.index].clone()),
+        }
+    }
 }
 
 struct IndexedSortDescriptor {
@@ -104,13 +633,13 @@ struct IndexedSortDescriptor {
     order: SortOrder,
 }
 
-fn compare(a: &Vec<CellValue>, b: &Vec<CellValue>, order: &Vec<IndexedSortDescriptor>) -> Ordering {
+fn compare(data: &[Column], a: usize, b: usize, order: &Vec<IndexedSortDescriptor>) -> Ordering {
     for (_, desc) in order.iter().enumerate() {
-        let col_index = desc.index;
-        let order = match desc.order {
-            SortOrder::Asc => a[col_index].cmp(&b[col_index]),
-            SortOrder::Desc => b[col_index].cmp(&a[col_index]),
-            SortOrder::None => Ordering::Equal,
+        let column = &data[desc.index];
+        let order = match desc.order.row_direction() {
+            Some(direction) if direction.abs => direction.apply(column.compare_abs(a, b)),
+            Some(direction) => direction.apply(column.compare(a, b)),
+            None => Ordering::Equal,
         };
 
         match order {
@@ -121,218 +650,501 @@ fn compare(a: &Vec<CellValue>, b: &Vec<CellValue>, order: &Vec<IndexedSortDescri
     return Ordering::Equal;
 }
 
-fn sort_table(table: &mut Table, sort: &Vec<SortDescriptor>) {
-    // create sort descriptors with column indices
-    let indexed_sort_descriptors: Vec<IndexedSortDescriptor> = sort
+// compares two groups by their aggregated values, for the sorted columns other than the
+// pivot columns, which order the groups by their keys instead
+fn compare_groups(
+    a: &PivotNode,
+    b: &PivotNode,
+    sort: &[IndexedSortDescriptor],
+    row_pivot_indices: &[usize],
+) -> Ordering {
+    for desc in sort
         .iter()
-        .map(|x| IndexedSortDescriptor {
-            order: x.order,
-            // look up the index of this column
-            index: table.index_for_column(&x.column),
-        })
-        .collect();
-
-    // sort the table (a bit yuck, shouldn't be mutating)
-    table
-        .data
-        .sort_by(|a, b| compare(&a, &b, &indexed_sort_descriptors));
+        .filter(|desc| !row_pivot_indices.contains(&desc.index))
+    {
+        let (a, b) = (&a.values[desc.index], &b.values[desc.index]);
+        let order = match desc.order.row_direction() {
+            Some(direction) => direction.compare(a, b),
+            None => Ordering::Equal,
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    Ordering::Equal
 }
 
-fn aggregate_rows(
-    table: &Table,
-    row_pivots: &Vec<String>,
-    accumulators: &Vec<Accumulator>,
-) -> Vec<PivotTableRow> {
-    // TODO - if there are not any row pivots, do we short-circuit some of this code
-
-    let data = &table.data;
-
-    // convert row pivot columns into indices
-    let row_pivot_indices: Vec<usize> = row_pivots
-        .iter()
-        .map(|s| table.index_for_column(s))
-        .collect();
+// compares two table rows, falling back to their order in the table so that
+// every row has a distinct position
+fn compare_rows(
+    data: &[Column],
+    a: usize,
+    b: usize,
+    order: &Vec<IndexedSortDescriptor>,
+) -> Ordering {
+    compare(data, a, b, order).then(a.cmp(&b))
+}
 
-    // key each columns
-    let keyed_rows: Vec<KeyedRow> = data
+fn pivot_orders(row_pivots: &[String], sort: &[SortDescriptor]) -> Vec<SortOrder> {
+    row_pivots
         .iter()
-        .map(|r| KeyedRow {
-            key: RowKey::new(r, &row_pivot_indices),
-            values: r,
-        })
-        .collect();
-
-    let groups = keyed_rows.into_iter().group_by(|a| a.key.clone());
-
-    // aggregate over each group
-    groups
-        .into_iter()
-        .map(|(key, group)| {
-            // compute the aggregate for this group
-            let materialised = group.collect::<Vec<KeyedRow>>();
-            let agg = materialised.iter().skip(1).fold(
-                RowAggregator::new(materialised[0].values, &accumulators),
-                |acc, row| acc.accumulate(&row.values),
-            );
-            let values = agg.to_row();
-            // map to a pivot row
-            PivotTableRow { key, values }
+        // use the sort order from the sort descriptors if present
+        .map(|column| match sort.iter().find(|x| x.column.eq(column)) {
+            Some(sort_desc) => sort_desc.order,
+            None => SortOrder::Asc,
         })
         .collect()
 }
 
-fn aggregate_totals(
-    aggregate_table: &mut Vec<PivotTableRow>,
-    depth: &usize,
-    accumulators: &Vec<Accumulator>,
-) {
-    let mut totals: Vec<IndexedPivotTableRow> = vec![];
-    let mut current_key = aggregate_table[0].key.clone();
-    let mut acc = RowAggregator::new(&aggregate_table[0].values, &accumulators);
-    let mut start_index = 0;
-    for (i, row) in aggregate_table.iter().skip(1).enumerate() {
-        if row.key.depth() - 1 == *depth {
-            if row.key.eq_depth(&current_key, depth) {
-                acc = acc.accumulate(&row.values);
+impl PivotTable {
+    pub fn new(
+        table: &Table,
+        rows: &[usize],
+        row_pivots: &[String],
+        column_pivots: &[String],
+        sort: &[SortDescriptor],
+        accumulators: &[Accumulator],
+        having: &[HavingDescriptor],
+    ) -> Result<PivotTable, Error> {
+        // create sort descriptors with column indices
+        let indexed_sort_descriptors = sort
+            .iter()
+            .map(|x| {
+                Ok(IndexedSortDescriptor {
+                    order: x.order,
+                    // look up the index of this column
+                    index: table.index_for_column(&x.column)?,
+                })
+            })
+            .collect::<Result<Vec<IndexedSortDescriptor>, Error>>()?;
+
+        // the filters for each depth are combined, where depths outside of the tree are
+        // ignored
+        let mut having_filters: Vec<Option<FilterExpr>> = vec![];
+        for depth in 0..=row_pivots.len() {
+            let filters = having
+                .iter()
+                .filter(|desc| depth > 0 && desc.depth == depth)
+                .map(|desc| FilterExpr::new(table, &desc.filter, desc.filter_op))
+                .collect::<Result<Vec<FilterExpr>, Error>>()?;
+            having_filters.push(if filters.is_empty() {
+                None
             } else {
-                totals.push(IndexedPivotTableRow {
-                    index: start_index,
-                    row: PivotTableRow {
-                        values: acc.to_row(),
-                        key: current_key.clone_depth(&depth),
-                    },
-                });
-                start_index = i;
-                acc = RowAggregator::new(&row.values, &accumulators);
-                current_key = row.key.clone();
+                Some(FilterExpr::all(filters))
+            });
+        }
+
+        let mut pivot_table = PivotTable {
+            sorted_rows: vec![],
+            root: PivotNode::new(vec![]),
+            row_pivot_indices: row_pivots
+                .iter()
+                .map(|s| table.index_for_column(s))
+                .collect::<Result<Vec<usize>, Error>>()?,
+            pivot_orders: pivot_orders(row_pivots, sort),
+            depth: row_pivots.len(),
+            sort: indexed_sort_descriptors,
+            aggregation: Aggregation {
+                accumulators: accumulators.to_vec(),
+                column_pivot_indices: column_pivots
+                    .iter()
+                    .map(|s| table.index_for_column(s))
+                    .collect::<Result<Vec<usize>, Error>>()?,
+                having: having_filters,
+            },
+            display_order: RefCell::new(None),
+            columns: table.columns.clone(),
+        };
+
+        // the initial rows are handled in the same way as rows which are later added
+        pivot_table.update(&table.data.borrow(), &[], rows);
+        Ok(pivot_table)
+    }
+
+    // updates the pivot table when rows are added, updated or removed, where the removed
+    // rows are given with the values they had when they were added, and updated rows are
+    // both removed and added. Only the groups which contain these rows are re-aggregated.
+    pub fn update(
+        &mut self,
+        data: &[Column],
+        removed: &[(usize, &Vec<CellValue>)],
+        added: &[usize],
+    ) -> UpdatedRows {
+        if self.row_pivot_indices.is_empty() {
+            return UpdatedRows::Rows(self.update_sorted_rows(data, removed, added));
+        }
+
+        *self.display_order.get_mut() = None;
+        let mut paths: BTreeSet<Vec<CellCode>> = BTreeSet::new();
+        for (row_index, previous) in removed {
+            let path: Vec<CellCode> = self
+                .row_pivot_indices
+                .iter()
+                .map(|i| data[*i].code_of(&previous[*i]))
+                .collect();
+            if let Some(leaf) = self.root.find_node(&path) {
+                if let Ok(position) = leaf.rows.binary_search(row_index) {
+                    leaf.rows.remove(position);
+                }
+            }
+            paths.insert(path);
+        }
+        for row_index in added {
+            let path: Vec<CellCode> = self
+                .row_pivot_indices
+                .iter()
+                .map(|i| data[*i].code(*row_index))
+                .collect();
+            let leaf = self.root.find_or_insert_leaf(&path);
+            if let Err(position) = leaf.rows.binary_search(row_index) {
+                leaf.rows.insert(position, *row_index);
+            }
+            paths.insert(path);
+        }
+
+        let paths: Vec<Vec<CellCode>> = paths.into_iter().collect();
+        self.root
+            .refresh(&paths, data, &self.row_pivot_indices, &self.aggregation);
+
+        // each of the groups above the leaves is re-aggregated too, up to the grand total
+        let mut updated: HashSet<Vec<CellCode>> = paths
+            .iter()
+            .flat_map(|path| (0..=path.len()).map(move |depth| path[..depth].to_vec()))
+            .collect();
+
+        self.root.show(
+            &paths,
+            data,
+            &self.row_pivot_indices,
+            &self.aggregation,
+            None,
+            &mut updated,
+        );
+
+        // only the children of the groups which changed are ordered again
+        let mut changed: HashMap<&[CellCode], Vec<CellCode>> = HashMap::new();
+        for path in &updated {
+            if let Some((code, parent)) = path.split_last() {
+                changed.entry(parent).or_default().push(*code);
+            }
+        }
+        for (parent, codes) in changed {
+            if let Some(node) = self.root.find_node(parent) {
+                node.update_order(
+                    &codes,
+                    data,
+                    &self.row_pivot_indices,
+                    &self.pivot_orders,
+                    &self.sort,
+                );
             }
         }
+        UpdatedRows::Groups(updated)
     }
-    totals.push(IndexedPivotTableRow {
-        index: start_index,
-        row: PivotTableRow {
-            values: acc.to_row(),
-            key: current_key.clone().clone_depth(&depth),
-        },
-    });
 
-    while totals.len() > 0 {
-        let item = totals.remove(totals.len() - 1);
-        aggregate_table.insert(item.index, item.row);
+    // patches the sort order, rather than re-sorting all of the rows, returning the
+    // added rows in sorted order
+    fn update_sorted_rows(
+        &mut self,
+        data: &[Column],
+        removed: &[(usize, &Vec<CellValue>)],
+        added: &[usize],
+    ) -> Vec<usize> {
+        let removed: HashSet<usize> = removed.iter().map(|(row_index, _)| *row_index).collect();
+        let sort = &self.sort;
+
+        let mut added = added.to_vec();
+        added.sort_by(|a, b| compare_rows(data, *a, *b, sort));
+
+        self.sorted_rows = self
+            .sorted_rows
+            .iter()
+            .cloned()
+            .filter(|row_index| !removed.contains(row_index))
+            .merge_by(added.iter().cloned(), |a, b| {
+                compare_rows(data, *a, *b, sort) != Ordering::Greater
+            })
+            .collect();
+        added
     }
-}
 
-fn sort_for_pivot(row_pivots: &Vec<String>, sort: &Vec<SortDescriptor>) -> Vec<SortDescriptor> {
-    row_pivots
-        .iter()
-        // create sort descriptors for each pivot
-        .map(|column| SortDescriptor {
-            column: column.clone(),
-            // use the sort order from the sort descriptors if present
-            order: match sort.iter().find(|x| x.column.eq(column)) {
-                Some(sort_desc) => sort_desc.order,
-                None => SortOrder::Asc,
-            },
+    // the paths of the groups of a pivoted view in the order they are displayed
+    fn display_order(&self) -> Ref<'_, [Vec<CellCode>]> {
+        if self.display_order.borrow().is_none() {
+            let mut paths = vec![];
+            // the grand total comes first, with an empty path
+            if !self.root.children.is_empty() && !self.root.hidden {
+                paths.push(vec![]);
+            }
+            self.root.flatten(self.depth, &mut paths);
+            *self.display_order.borrow_mut() = Some(paths);
+        }
+        Ref::map(self.display_order.borrow(), |paths| {
+            paths.as_deref().unwrap_or_default()
         })
-        // combine with the sort descriptors
-        .chain(sort.iter().cloned())
-        .collect::<Vec<SortDescriptor>>()
-}
+    }
 
-impl PivotTable {
-    pub fn new(
-        table: &mut Table,
-        row_pivots: &Vec<String>,
-        sort: &Vec<SortDescriptor>,
-        accumulators: &Vec<Accumulator>,
-    ) -> PivotTable {
-        if row_pivots.len() > 0 {
-            // sort based on pivot information
-            let pivot_sort = sort_for_pivot(row_pivots, sort);
-            sort_table(table, &pivot_sort);
-
-            // aggregate over the 'raw' rows to create totals
-            let mut aggregate_table = aggregate_rows(table, &row_pivots, &accumulators);
-
-            let total_accumulators: Vec<Accumulator> =
-                accumulators.iter().map(|s| s.total_accumulator()).collect();
-
-            // add the totals for each level of pivot
-            for d in (0..row_pivots.len()).rev() {
-                aggregate_totals(&mut aggregate_table, &d, &total_accumulators);
-            }
+    // the path and values of each row between the given positions, in the order they
+    // are displayed, where there are none if the end comes before the start
+    fn output_rows(&self, start: usize, end: usize) -> Vec<(&[CellCode], OutputRow<'_>)> {
+        if self.row_pivot_indices.is_empty() {
+            return self
+                .sorted_rows
+                .iter()
+                .skip(start)
+                .take(end.saturating_sub(start))
+                .map(|row_index| (&[][..], OutputRow::Row(*row_index)))
+                .collect();
+        }
+        let display_order = self.display_order();
+        let rows = display_order
+            .iter()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .filter_map(|path| self.root.get_node(path))
+            .map(|node| (&node.path[..], OutputRow::Group(node)))
+            .collect();
+        rows
+    }
+
+    // follows the rows of the table as they are renumbered, where the new indices are
+    // in the same order as the old ones so the rows stay sorted
+    pub fn renumber_rows(&mut self, row_indices: &[usize]) {
+        for row_index in self.sorted_rows.iter_mut() {
+            *row_index = row_indices[*row_index];
+        }
+        self.root.renumber_rows(row_indices);
+    }
+
+    // expands the groups with paths up to the given length, and collapses the rest
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.root.reset_expanded();
+        *self.display_order.get_mut() = None;
+    }
+
+    // expands or collapses the group shown at the given row, where the grand total, the
+    // rows of views without pivots and rows which are out of range are left as they are
+    pub fn set_expanded(&mut self, row: usize, expanded: bool) {
+        if self.row_pivot_indices.is_empty() {
+            return;
+        }
+        let path = match self.display_order().get(row) {
+            Some(path) if !path.is_empty() => path.clone(),
+            _ => return,
+        };
+        if let Some(node) = self.root.find_node(&path) {
+            node.expanded = Some(expanded);
+        }
+        *self.display_order.get_mut() = None;
+    }
 
-            PivotTable {
-                rows: aggregate_table,
-                columns: table.columns.clone(),
+    // whether the children of the group shown at the given row are shown
+    pub fn is_expanded(&self, row: usize) -> bool {
+        match self.output_rows(row, row + 1).first() {
+            Some((_, OutputRow::Group(node))) => {
+                node.path.is_empty() || node.is_expanded(self.depth)
             }
+            _ => false,
+        }
+    }
+
+    // the combinations of values of the column pivots which appear in the view, in
+    // ascending order
+    fn split_paths(&self, data: &[Column]) -> Vec<Vec<CellCode>> {
+        let mut paths: Vec<Vec<CellCode>> = if self.row_pivot_indices.is_empty() {
+            let paths: HashSet<Vec<CellCode>> = self
+                .sorted_rows
+                .iter()
+                .map(|row_index| self.aggregation.split_path(data, *row_index))
+                .collect();
+            paths.into_iter().collect()
+        } else if self.root.children.is_empty() {
+            vec![]
         } else {
-            // when there are no pivots present - we short cut some of the logic above
+            self.root.splits.keys().cloned().collect()
+        };
+        let columns: Vec<&Column> = self
+            .aggregation
+            .column_pivot_indices
+            .iter()
+            .map(|i| &data[*i])
+            .collect();
+        paths.sort_by(|a, b| {
+            a.iter()
+                .zip(b.iter())
+                .zip(columns.iter())
+                .map(|((a, b), column)| column.compare_codes(*a, *b))
+                .find(|ordering| *ordering != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
 
-            // sort
-            sort_table(table, &sort);
+        // the columns can also be sorted by their totals, where the sort is stable so
+        // that columns with equal totals keep the order of their values
+        let column_sorts: Vec<(usize, SortDirection)> = self
+            .sort
+            .iter()
+            .filter_map(|desc| Some((desc.index, desc.order.column_direction()?)))
+            .collect();
+        if !column_sorts.is_empty() {
+            let totals: HashMap<Vec<CellCode>, Vec<CellValue>> =
+                if self.row_pivot_indices.is_empty() {
+                    self.aggregation
+                        .split_rows(data, &self.sorted_rows)
+                        .into_iter()
+                        .map(|(path, split)| (path, split.values))
+                        .collect()
+                } else {
+                    self.root
+                        .splits
+                        .iter()
+                        .map(|(path, split)| (path.clone(), split.values.clone()))
+                        .collect()
+                };
+            paths.sort_by(|a, b| {
+                column_sorts
+                    .iter()
+                    .map(|(index, direction)| {
+                        direction.compare(&totals[a][*index], &totals[b][*index])
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        paths
+    }
 
-            // map to the pivot structure
-            let data = &table.data;
-            let rows = data
-                .iter()
-                .map(|s| PivotTableRow {
-                    key: RowKey::empty(),
-                    values: s.clone(),
+    // the output columns for the given columns of the table, in the order they are given
+    fn output_columns(&self, data: &[Column], columns: &[String]) -> Vec<OutputColumn> {
+        let indexed: Vec<(usize, &String)> = columns
+            .iter()
+            .filter_map(|col| Some((self.columns.iter().position(|x| x.eq(col))?, col)))
+            .collect();
+        if self.aggregation.column_pivot_indices.is_empty() {
+            return indexed
+                .into_iter()
+                .map(|(index, col)| OutputColumn {
+                    name: col.clone(),
+                    index,
+                    split: None,
                 })
                 .collect();
+        }
+        self.split_paths(data)
+            .into_iter()
+            .flat_map(|split| {
+                let prefix = RowKey::new(data, &self.aggregation.column_pivot_indices, &split)
+                    .values
+                    .iter()
+                    .map(|value| value.label())
+                    .join("|");
+                indexed
+                    .iter()
+                    .map(|(index, col)| OutputColumn {
+                        name: format!("{}|{}", prefix, col),
+                        index: *index,
+                        split: Some(split.clone()),
+                    })
+                    .collect::<Vec<OutputColumn>>()
+            })
+            .collect()
+    }
 
-            PivotTable {
-                rows,
-                columns: table.columns.clone(),
-            }
+    // the names of the output columns, where column pivots expand each of the given
+    // columns into a column for each combination of their values
+    pub fn column_names(&self, data: &[Column], columns: &[String]) -> Vec<String> {
+        self.output_columns(data, columns)
+            .into_iter()
+            .map(|column| column.name)
+            .collect()
+    }
+
+    pub fn num_rows(&self) -> usize {
+        if self.row_pivot_indices.is_empty() {
+            self.sorted_rows.len()
+        } else {
+            self.display_order().len()
         }
     }
 
-    pub fn to_serializable_rows(&self) -> SerializablePivotTable {
-        let rows: Vec<HashMap<String, &CellValue>> = self
-            .rows
+    pub fn to_serializable_rows(&self, data: &[Column]) -> SerializablePivotTable {
+        let output_rows = self.output_rows(0, usize::MAX);
+        let output_columns = self.output_columns(data, &self.columns);
+        let rows: Vec<HashMap<String, CellValue>> = output_rows
             .iter()
-            .map(|row| {
-                let mut map: HashMap<String, &CellValue> = HashMap::new();
-                for (column_index, col) in self.columns.iter().enumerate() {
-                    map.insert(col.to_string(), &row.values[column_index]);
+            .map(|(_, row)| {
+                let mut map: HashMap<String, CellValue> = HashMap::new();
+                for column in output_columns.iter() {
+                    map.insert(
+                        column.name.clone(),
+                        row.column_value(data, &self.aggregation, column),
+                    );
                 }
                 map
             })
             .collect();
-        let row_paths = self.rows.iter().map(|s| s.key.clone()).collect();
+        let row_paths = output_rows
+            .iter()
+            .map(|(path, _)| RowKey::new(data, &self.row_pivot_indices, path))
+            .collect();
         SerializablePivotTable { rows, row_paths }
     }
 
     pub fn to_serializable_columns(
         &self,
+        data: &[Column],
         columns: &Vec<String>,
         options: &ViewOptions,
     ) -> SerializableColumnarPivotTable {
-        let columns: HashMap<String, Vec<&CellValue>> = self
-            .columns
-            .iter()
-            .filter(|col| columns.iter().any(|i| i.eq(*col)))
-            .enumerate()
-            .map(|(idx, col)| {
-                let col_data: Vec<&CellValue> = self
-                    .rows
+        let output_rows = self.output_rows(options.start_row, options.end_row);
+        self.serialize_columns(data, &output_rows, columns)
+    }
+
+    // the rows which were added or changed by an update, in the order they are displayed
+    pub fn to_serializable_delta(
+        &self,
+        data: &[Column],
+        updated: &UpdatedRows,
+        columns: &[String],
+    ) -> SerializableColumnarPivotTable {
+        let output_rows: Vec<(&[CellCode], OutputRow)> = match updated {
+            UpdatedRows::Rows(rows) => rows
+                .iter()
+                .map(|row_index| (&[][..], OutputRow::Row(*row_index)))
+                .collect(),
+            UpdatedRows::Groups(paths) => self
+                .display_order()
+                .iter()
+                .filter(|path| paths.contains(*path))
+                .filter_map(|path| self.root.get_node(path))
+                .map(|node| (&node.path[..], OutputRow::Group(node)))
+                .collect(),
+        };
+        self.serialize_columns(data, &output_rows, columns)
+    }
+
+    // copies the requested columns out of the output rows
+    fn serialize_columns(
+        &self,
+        data: &[Column],
+        output_rows: &[(&[CellCode], OutputRow)],
+        columns: &[String],
+    ) -> SerializableColumnarPivotTable {
+        let columns: HashMap<String, Vec<CellValue>> = self
+            .output_columns(data, columns)
+            .into_iter()
+            .map(|column| {
+                let col_data: Vec<CellValue> = output_rows
                     .iter()
-                    .skip(options.start_row)
-                    .take(options.end_row - options.start_row)
-                    .map(|r| &r.values[idx])
+                    .map(|(_, row)| row.column_value(data, &self.aggregation, &column))
                     .collect();
-                (col.clone(), col_data)
+                (column.name, col_data)
             })
             .collect();
 
-        let row_paths = self
-            .rows
+        let row_paths = output_rows
             .iter()
-            .skip(options.start_row)
-            .take(options.end_row - options.start_row)
-            .map(|s| s.key.clone())
+            .map(|(path, _)| RowKey::new(data, &self.row_pivot_indices, path))
             .collect();
 
         SerializableColumnarPivotTable { columns, row_paths }
@@ -1,47 +1,426 @@
-use super::accumulator::Accumulator;
-use super::cell_value::CellValue;
+use super::accumulator::{Accumulator, AccumulatorState};
+use super::cell_value::{compare_floats, CellValue};
+use super::column::Column;
+use std::collections::HashMap;
 
+#[derive(Default)]
 pub struct RowAggregator {
     row: Vec<CellAccumulator>,
 }
 
 struct CellAccumulator {
-    value: CellValue,
+    state: AccumulatorState,
     accumulator: Accumulator,
 }
 
-impl CellAccumulator {
-    fn accumulate(&self, acc: &CellValue) -> CellAccumulator {
-        CellAccumulator {
-            value: self.value.accumulate(acc, &self.accumulator),
-            accumulator: self.accumulator.clone(),
+// accumulates the values of one column of the given rows
+fn accumulate(
+    columns: &[Column],
+    column: &Column,
+    rows: &[usize],
+    accumulator: &Accumulator,
+) -> AccumulatorState {
+    let numbers = || rows.iter().filter_map(|row| column.as_f64(*row));
+    match accumulator {
+        Accumulator::Mean => AccumulatorState::Mean {
+            sum: numbers().sum(),
+            count: numbers().count(),
+        },
+        Accumulator::WeightedMean(weights) => {
+            let weights = &columns[*weights];
+            let (sum, weights) = rows
+                .iter()
+                .filter_map(|row| Some((column.as_f64(*row)?, weights.as_f64(*row)?)))
+                .fold((0.0, 0.0), |(sum, total), (value, weight)| {
+                    (sum + value * weight, total + weight)
+                });
+            AccumulatorState::WeightedMean { sum, weights }
         }
+        Accumulator::Median => {
+            let mut values: Vec<f64> = numbers().collect();
+            values.sort_by(|a, b| compare_floats(*a, *b));
+            AccumulatorState::Sorted(values)
+        }
+        Accumulator::DistinctCount
+        | Accumulator::Unique
+        | Accumulator::Dominant
+        | Accumulator::Join(_) => {
+            let mut counts = HashMap::new();
+            for row in rows.iter().filter(|row| column.is_valid(**row)) {
+                *counts.entry(column.get(*row)).or_insert(0) += 1;
+            }
+            AccumulatorState::Counts(counts)
+        }
+        Accumulator::StdDev { .. } | Accumulator::Var { .. } => {
+            let (mut count, mut mean, mut m2) = (0, 0.0, 0.0);
+            for value in numbers() {
+                count += 1;
+                let delta = value - mean;
+                mean += delta / count as f64;
+                m2 += delta * (value - mean);
+            }
+            AccumulatorState::Moments { count, mean, m2 }
+        }
+        Accumulator::PctSumParent | Accumulator::PctSumGrandTotal => {
+            AccumulatorState::Value(column.aggregate(rows, &Accumulator::Sum))
+        }
+        Accumulator::First(sequence) => pick(column, rows, |row| columns[*sequence].get(row), true),
+        Accumulator::Last(sequence) => pick(column, rows, |row| columns[*sequence].get(row), false),
+        Accumulator::LastByIndex(Some(index)) => {
+            pick(column, rows, |row| columns[*index].get(row), false)
+        }
+        Accumulator::LastByIndex(None) => {
+            pick(column, rows, |row| CellValue::Integer(row as i64), false)
+        }
+        _ => AccumulatorState::Value(column.aggregate(rows, accumulator)),
+    }
+}
+
+// picks the value of the row with the lowest (or highest) key
+fn pick(
+    column: &Column,
+    rows: &[usize],
+    key: impl Fn(usize) -> CellValue,
+    lowest: bool,
+) -> AccumulatorState {
+    let keyed = rows.iter().map(|row| (key(*row), *row));
+    let (key, row) = if lowest {
+        keyed.min_by(|a, b| a.0.cmp(&b.0))
+    } else {
+        keyed.max_by(|a, b| a.0.cmp(&b.0))
+    }
+    .unwrap();
+    AccumulatorState::Picked {
+        key,
+        value: column.get(row),
     }
 }
 
 impl RowAggregator {
-    pub fn new(source: &Vec<CellValue>, accumulators: &Vec<Accumulator>) -> RowAggregator {
-        let row = source
+    // aggregates the given rows of a table, a column at a time
+    pub fn from_columns(
+        columns: &[Column],
+        rows: &[usize],
+        accumulators: &[Accumulator],
+    ) -> RowAggregator {
+        let row = columns
             .iter()
             .zip(accumulators.iter())
-            .map(|(cell, acc)| CellAccumulator {
-                value: cell.seed_value(acc),
+            .map(|(column, acc)| CellAccumulator {
+                state: accumulate(columns, column, rows, acc),
                 accumulator: *acc,
             })
             .collect();
         RowAggregator { row }
     }
 
-    pub fn accumulate(&self, values: &Vec<CellValue>) -> RowAggregator {
-        let mut row: Vec<CellAccumulator> = vec![];
-        // TODO - try zip
-        for (i, new_cell) in values.iter().enumerate() {
-            row.push(self.row[i].accumulate(&new_cell));
-        }
+    // combines the aggregates of (non-empty) groups of rows into the aggregate of all
+    // of the rows, without revisiting them
+    pub fn merge(aggregators: &[&RowAggregator]) -> RowAggregator {
+        let (first, rest) = aggregators.split_first().unwrap();
+        let row = first
+            .row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| CellAccumulator {
+                state: rest.iter().fold(cell.state.clone(), |state, aggregator| {
+                    state.merge(&aggregator.row[i].state, &cell.accumulator)
+                }),
+                accumulator: cell.accumulator,
+            })
+            .collect();
         RowAggregator { row }
     }
 
+    // the value of a single column
+    pub fn value(&self, column: usize) -> CellValue {
+        let cell = &self.row[column];
+        cell.state.value(&cell.accumulator)
+    }
+
     pub fn to_row(&self) -> Vec<CellValue> {
-        self.row.iter().map(|x| x.value.clone()).collect()
+        self.row
+            .iter()
+            .map(|x| x.state.value(&x.accumulator))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ColumnType;
+
+    const ACCUMULATORS: [Accumulator; 4] = [
+        Accumulator::Var { sample: false },
+        Accumulator::Var { sample: true },
+        Accumulator::StdDev { sample: false },
+        Accumulator::StdDev { sample: true },
+    ];
+
+    // a column for each of the accumulators, holding the same values
+    fn columns(values: &[Option<f64>]) -> Vec<Column> {
+        ACCUMULATORS
+            .iter()
+            .map(|_| {
+                let mut column = Column::new(ColumnType::Float);
+                for value in values {
+                    column.push(&value.map_or(CellValue::Null, CellValue::Float));
+                }
+                column
+            })
+            .collect()
+    }
+
+    // the variance and standard deviation worked out the naive way, with one pass for the
+    // mean and another for the squared differences from it
+    fn two_pass(values: &[Option<f64>]) -> Vec<Option<f64>> {
+        let values: Vec<f64> = values.iter().filter_map(|value| *value).collect();
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        let squares: f64 = values.iter().map(|value| (value - mean).powi(2)).sum();
+        let var = |divisor: f64| {
+            if divisor > 0.0 {
+                Some(squares / divisor)
+            } else {
+                None
+            }
+        };
+        let (pop, sample) = (var(count), var(count - 1.0));
+        vec![pop, sample, pop.map(f64::sqrt), sample.map(f64::sqrt)]
+    }
+
+    fn assert_close(actual: Vec<CellValue>, expected: Vec<Option<f64>>) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            match (actual, expected) {
+                (CellValue::Float(actual), Some(expected)) => assert!(
+                    (actual - expected).abs() <= 1e-9 * expected.abs().max(1.0),
+                    "{} != {}",
+                    actual,
+                    expected
+                ),
+                (CellValue::Null, None) => {}
+                (actual, expected) => panic!("{:?} != {:?}", actual.label(), expected),
+            }
+        }
+    }
+
+    // values around a million with a small spread, which a naive single pass over the
+    // sums of squares would lose the precision of, and a few missing values
+    fn values(count: usize) -> Vec<Option<f64>> {
+        let mut seed: u64 = 42;
+        (0..count)
+            .map(|i| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                if i % 7 == 3 {
+                    None
+                } else {
+                    Some(1e6 + (seed >> 40) as f64 / (1u64 << 24) as f64)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn var_and_stddev_match_two_passes() {
+        for count in [0, 1, 2, 3, 10, 100].iter() {
+            let values = values(*count);
+            let rows: Vec<usize> = (0..values.len()).collect();
+            let aggregator = RowAggregator::from_columns(&columns(&values), &rows, &ACCUMULATORS);
+            assert_close(aggregator.to_row(), two_pass(&values));
+        }
+    }
+
+    #[test]
+    fn merged_var_and_stddev_match_two_passes() {
+        let values = values(100);
+        let columns = columns(&values);
+        // groups of different sizes, including some without any values
+        let mut groups: Vec<Vec<usize>> = vec![vec![3], vec![10], vec![17, 24]];
+        let mut start = 25;
+        for size in [1, 2, 5, 13, 30].iter().cycle() {
+            if start >= values.len() {
+                break;
+            }
+            let end = (start + size).min(values.len());
+            groups.push((start..end).collect());
+            start = end;
+        }
+        groups.push((0..3).collect());
+        groups.push((4..10).collect());
+        groups.push((11..17).collect());
+        groups.push((18..24).collect());
+
+        let aggregators: Vec<RowAggregator> = groups
+            .iter()
+            .map(|rows| RowAggregator::from_columns(&columns, rows, &ACCUMULATORS))
+            .collect();
+        let aggregators: Vec<&RowAggregator> = aggregators.iter().collect();
+        assert_close(
+            RowAggregator::merge(&aggregators).to_row(),
+            two_pass(&values),
+        );
+
+        // merging the merged groups gives the same result
+        let (left, right) = aggregators.split_at(aggregators.len() / 2);
+        let merged =
+            RowAggregator::merge(&[&RowAggregator::merge(left), &RowAggregator::merge(right)]);
+        assert_close(merged.to_row(), two_pass(&values));
+    }
+
+    // a column of the given type holding the given values
+    fn column(column_type: ColumnType, values: &[CellValue]) -> Column {
+        let mut column = Column::new(column_type);
+        for value in values {
+            column.push(value);
+        }
+        column.sort_dictionary();
+        column
+    }
+
+    fn strings(values: &[Option<&str>]) -> Column {
+        let values: Vec<CellValue> = values
+            .iter()
+            .map(|value| value.map_or(CellValue::Null, |value| CellValue::Str(value.to_string())))
+            .collect();
+        column(ColumnType::String, &values)
+    }
+
+    // the values of the accumulators over each group of rows, and over all of them,
+    // both aggregated directly and merged from the groups
+    fn aggregate(
+        column: &Column,
+        groups: &[Vec<usize>],
+        accumulators: &[Accumulator],
+    ) -> (Vec<Vec<CellValue>>, Vec<CellValue>) {
+        let aggregate = |rows: &[usize]| {
+            let columns: Vec<&Column> = accumulators.iter().map(|_| column).collect();
+            let row = columns
+                .iter()
+                .zip(accumulators.iter())
+                .map(|(column, accumulator)| CellAccumulator {
+                    state: accumulate(&[], column, rows, accumulator),
+                    accumulator: *accumulator,
+                })
+                .collect();
+            RowAggregator { row }
+        };
+        let aggregators: Vec<RowAggregator> = groups.iter().map(|rows| aggregate(rows)).collect();
+        let all: Vec<usize> = groups.concat();
+        let total = aggregate(&all).to_row();
+        let merged = RowAggregator::merge(&aggregators.iter().collect::<Vec<_>>()).to_row();
+        assert_eq!(total, merged);
+        (
+            aggregators
+                .iter()
+                .map(|aggregator| aggregator.to_row())
+                .collect(),
+            total,
+        )
+    }
+
+    #[test]
+    fn distinct_count_unique_and_dominant_merge_the_values_of_their_groups() {
+        let events = strings(&[
+            Some("100m"),
+            Some("200m"),
+            Some("100m"),
+            None,
+            Some("200m"),
+            Some("200m"),
+            Some("relay"),
+            Some("relay"),
+        ]);
+        let accumulators = [
+            Accumulator::DistinctCount,
+            Accumulator::Unique,
+            Accumulator::Dominant,
+        ];
+        let str = |value: &str| CellValue::Str(value.to_string());
+        let (groups, total) = aggregate(
+            &events,
+            &[vec![0, 1, 2], vec![3, 4, 5], vec![6, 7], vec![3]],
+            &accumulators,
+        );
+        assert_eq!(
+            groups,
+            vec![
+                vec![CellValue::Integer(2), CellValue::Null, str("100m")],
+                vec![CellValue::Integer(1), str("200m"), str("200m")],
+                vec![CellValue::Integer(1), str("relay"), str("relay")],
+                vec![CellValue::Integer(0), CellValue::Null, CellValue::Null],
+            ]
+        );
+        // the groups' values are merged rather than their counts summed, and ties go to
+        // the lowest value
+        assert_eq!(
+            total,
+            vec![CellValue::Integer(3), CellValue::Null, str("200m")]
+        );
+        let (_, total) = aggregate(&events, &[vec![0, 2], vec![6, 7]], &accumulators);
+        assert_eq!(
+            total,
+            vec![CellValue::Integer(2), CellValue::Null, str("100m")]
+        );
+    }
+
+    #[test]
+    fn join_lists_the_distinct_values_of_a_group_up_to_its_limit() {
+        let events = strings(&[
+            Some("relay"),
+            Some("200m"),
+            None,
+            Some("100m"),
+            Some("200m"),
+        ]);
+        let str = |value: &str| CellValue::Str(value.to_string());
+        let (groups, total) = aggregate(
+            &events,
+            &[vec![0, 1], vec![2], vec![3, 4]],
+            &[Accumulator::Join(2), Accumulator::Join(5), Accumulator::Any],
+        );
+        assert_eq!(
+            groups,
+            vec![
+                vec![str("200m, relay"), str("200m, relay"), str("relay")],
+                vec![CellValue::Null, CellValue::Null, CellValue::Null],
+                vec![str("100m, 200m"), str("100m, 200m"), str("100m")],
+            ]
+        );
+        assert_eq!(
+            total[..2],
+            [str("100m, 200m, ..."), str("100m, 200m, relay")]
+        );
+        assert!(!total[2].is_null());
+    }
+
+    #[test]
+    fn and_and_or_combine_the_booleans_of_a_group() {
+        let bools = column(
+            ColumnType::Boolean,
+            &[
+                CellValue::Bool(true),
+                CellValue::Bool(true),
+                CellValue::Null,
+                CellValue::Bool(false),
+                CellValue::Bool(false),
+            ],
+        );
+        let accumulators = [Accumulator::And, Accumulator::Or];
+        let (groups, total) =
+            aggregate(&bools, &[vec![0, 1], vec![2], vec![2, 3, 4]], &accumulators);
+        assert_eq!(
+            groups,
+            vec![
+                vec![CellValue::Bool(true), CellValue::Bool(true)],
+                vec![CellValue::Null, CellValue::Null],
+                vec![CellValue::Bool(false), CellValue::Bool(false)],
+            ]
+        );
+        assert_eq!(total, vec![CellValue::Bool(false), CellValue::Bool(true)]);
+
+        // they have no value for columns of other types
+        let (groups, _) = aggregate(&strings(&[Some("true")]), &[vec![0]], &accumulators);
+        assert_eq!(groups, vec![vec![CellValue::Null, CellValue::Null]]);
     }
 }
@@ -0,0 +1,80 @@
+use serde_json::Value;
+use std::fmt;
+
+use super::temporal;
+
+// the type of a column, using the same names as Perspective's schemas,
+// e.g. {"x": "integer", "y": "string"}
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Integer,
+    Float,
+    String,
+    Boolean,
+    Date,
+    Datetime,
+}
+
+impl ColumnType {
+    // infers the type of a column from the values it holds, where columns of
+    // mixed types are treated as strings
+    pub fn infer(rows: &[Value], column: &str) -> ColumnType {
+        use ColumnType::*;
+
+        let values = || rows.iter().map(move |row| &row[column]);
+
+        // ISO-8601 strings, optionally mixed with epoch milliseconds
+        let mut temporal = None;
+        for value in values() {
+            match value {
+                Value::String(s) if temporal::parse_date(s).is_some() => {
+                    temporal = temporal.or(Some(Date));
+                }
+                Value::String(s) if temporal::parse_datetime(s).is_some() => {
+                    temporal = Some(Datetime);
+                }
+                Value::Number(_) | Value::Null => {}
+                _ => {
+                    temporal = None;
+                    break;
+                }
+            }
+        }
+        if let Some(column_type) = temporal {
+            return column_type;
+        }
+
+        let mut column_type = None;
+        for value in values() {
+            let value_type = match value {
+                Value::Null => continue,
+                Value::Bool(_) => Boolean,
+                Value::Number(number) if number.is_i64() => Integer,
+                Value::Number(_) => Float,
+                _ => return String,
+            };
+            column_type = match (column_type, value_type) {
+                (None, value_type) => Some(value_type),
+                (Some(Integer), Float) | (Some(Float), Integer) => Some(Float),
+                (Some(current), value_type) if current == value_type => Some(current),
+                _ => return String,
+            };
+        }
+        column_type.unwrap_or(String)
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::String => "string",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Date => "date",
+            ColumnType::Datetime => "datetime",
+        };
+        f.write_str(name)
+    }
+}
@@ -1,32 +1,344 @@
 use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::Iterator;
+use std::rc::{Rc, Weak};
 use wasm_bindgen::prelude::*;
 
 use super::cell_value::CellValue;
+use super::column::Column;
+use super::config::Config;
+use super::error::Error;
+use super::schema::ColumnType;
 use super::utils::*;
-use super::view::View;
+use super::validation::{validate_config, ConfigProblem};
+use super::view::{View, ViewState};
 
 // describes a tabular structure with columns (described by strings)
-// and a two-dimensional array of data
+// and the values of each column
 #[wasm_bindgen]
 pub struct Table {
-    // TODO - explroe implementing IntoIterator for this type
+    // the columns are shared with the views created from this table, followed by columns
+    // holding the sequence numbers of each row's latest insert or update, and of the
+    // insert which added it
     #[wasm_bindgen(skip)]
-    pub data: Vec<Vec<CellValue>>,
+    pub data: Rc<RefCell<Vec<Column>>>,
     #[wasm_bindgen(skip)]
     pub columns: Vec<String>,
+    #[wasm_bindgen(skip)]
+    pub types: Vec<ColumnType>,
+    // the column used as a primary key, if any
+    index: Option<usize>,
+    // maps primary key values to their row
+    primary_keys: HashMap<CellValue, usize>,
+    // removed rows are left in place (and skipped over) until they are compacted, so
+    // that the indices of the remaining rows are stable between compactions
+    removed: Vec<bool>,
+    // the sequence number given to the next row which is inserted or updated
+    sequence: i64,
+    // views which are kept up to date as rows are added, updated and removed
+    views: Vec<Weak<RefCell<ViewState>>>,
+}
+
+// describes a row which has been added, updated or removed, where `previous` holds the
+// values of the row before the change (or None if the row is new)
+pub struct RowChange {
+    pub row_index: usize,
+    pub previous: Option<Vec<CellValue>>,
+    pub removed: bool,
 }
 
 impl Table {
-    pub fn index_for_column(&self, col_name: &String) -> usize {
-        self.columns.iter().position(|y| y.eq(col_name)).unwrap()
+    pub fn with_schema(
+        schema: Vec<(String, ColumnType)>,
+        index: Option<String>,
+    ) -> Result<Table, Error> {
+        let (columns, types): (Vec<String>, Vec<ColumnType>) = schema.into_iter().unzip();
+        let index = match index {
+            Some(index) => Some(
+                columns
+                    .iter()
+                    .position(|col| col.eq(&index))
+                    .ok_or(Error::UnknownColumn(index))?,
+            ),
+            None => None,
+        };
+        Ok(Table {
+            data: Rc::new(RefCell::new(
+                types
+                    .iter()
+                    .chain([ColumnType::Integer, ColumnType::Integer].iter())
+                    .map(|column_type| Column::new(*column_type))
+                    .collect(),
+            )),
+            columns,
+            types,
+            index,
+            primary_keys: HashMap::new(),
+            removed: vec![],
+            sequence: 0,
+            views: vec![],
+        })
+    }
+
+    pub fn index_for_column(&self, col_name: &String) -> Result<usize, Error> {
+        self.columns
+            .iter()
+            .position(|y| y.eq(col_name))
+            .ok_or_else(|| Error::UnknownColumn(col_name.clone()))
+    }
+
+    // the column used as a primary key, if any
+    pub fn index_column(&self) -> Option<usize> {
+        self.index
+    }
+
+    // the column holding the sequence numbers of the latest writes to the rows, which
+    // follows the columns of the schema
+    pub fn sequence_column(&self) -> usize {
+        self.columns.len()
+    }
+
+    // the column holding the sequence numbers the rows were inserted with, which unlike
+    // the sequence column don't change when the rows are updated
+    pub fn insertion_column(&self) -> usize {
+        self.columns.len() + 1
+    }
+
+    // the indices of the rows which haven't been removed
+    pub fn row_indices(&self) -> Vec<usize> {
+        (0..self.removed.len())
+            .filter(|row_index| !self.removed[*row_index])
+            .collect()
+    }
+
+    // copies the values of a row out of the columns
+    pub fn row_values(&self, row_index: usize) -> Vec<CellValue> {
+        self.data
+            .borrow()
+            .iter()
+            .map(|column| column.get(row_index))
+            .collect()
+    }
+
+    // checks a view config against the columns of the table, returning every problem
+    // found, or an error if the config can't be parsed at all
+    pub fn validate_view_config_with(&self, config: &str) -> Result<Vec<ConfigProblem>, Error> {
+        let config = Config::new(config.to_string())?;
+        Ok(validate_config(self, &config))
+    }
+
+    pub fn register_view(&mut self, view: &Rc<RefCell<ViewState>>) {
+        self.views.push(Rc::downgrade(view));
+    }
+
+    // passes the changes on to each of the views, forgetting those which have been dropped
+    fn notify_views(&mut self, changes: BTreeMap<usize, RowChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        let changes: Vec<RowChange> = changes.into_values().collect();
+        self.views.retain(|view| view.upgrade().is_some());
+        for view in self.views.iter().filter_map(|view| view.upgrade()) {
+            // the view is released before its callbacks run
+            let pending = view.borrow_mut().apply(&changes);
+            pending.notify();
+        }
+    }
+
+    // removes the rows with the given primary keys, ignoring any keys which are not present.
+    // The removed rows are dropped from the columns once they outnumber the remaining
+    // rows, although their strings are kept in the column dictionaries.
+    pub fn remove_rows(&mut self, keys: &[Value]) -> Result<(), Error> {
+        let index = self.index.ok_or_else(|| {
+            Error::InvalidConfig("rows can only be removed from a table with an index".to_string())
+        })?;
+
+        let column_type = &self.types[index];
+        let keys = keys
+            .iter()
+            .map(|key| {
+                CellValue::coerce(key, column_type).ok_or_else(|| {
+                    Error::TypeMismatch(format!(
+                        "expected a key of type {} but got {}",
+                        column_type, key
+                    ))
+                })
+            })
+            .collect::<Result<Vec<CellValue>, Error>>()?;
+
+        let mut changes: BTreeMap<usize, RowChange> = BTreeMap::new();
+        for key in keys {
+            if let Some(row_index) = self.primary_keys.remove(&key) {
+                self.removed[row_index] = true;
+                let previous = self.row_values(row_index);
+                record_change(&mut changes, row_index, Some(previous)).removed = true;
+            }
+        }
+        self.notify_views(changes);
+        self.compact();
+        Ok(())
+    }
+
+    // drops the removed rows if there are more of them than remaining rows, renumbering
+    // the remaining rows (which keep their order) in the table and its views
+    fn compact(&mut self) {
+        let size = self.size();
+        if self.removed.len() - size <= size {
+            return;
+        }
+        let keep: Vec<bool> = self.removed.iter().map(|removed| !removed).collect();
+        let mut row_indices = vec![0; keep.len()];
+        let mut next = 0;
+        for (row_index, kept) in keep.iter().enumerate() {
+            if *kept {
+                row_indices[row_index] = next;
+                next += 1;
+            }
+        }
+
+        for column in self.data.borrow_mut().iter_mut() {
+            column.retain(&keep);
+        }
+        for row_index in self.primary_keys.values_mut() {
+            *row_index = row_indices[*row_index];
+        }
+        self.removed = vec![false; size];
+
+        self.views.retain(|view| view.upgrade().is_some());
+        for view in self.views.iter().filter_map(|view| view.upgrade()) {
+            view.borrow_mut().renumber_rows(&row_indices);
+        }
+    }
+
+    // coerces each row to the type of its columns, then appends them to the table or, for
+    // tables with an index, upserts them by primary key. If any of the values cannot be
+    // coerced none of the rows are applied.
+    pub fn update_rows(&mut self, rows: &[Value]) -> Result<(), Error> {
+        let mut coerced: Vec<Vec<Option<CellValue>>> = vec![];
+        let mut errors: Vec<String> = vec![];
+        for (row_index, row) in rows.iter().enumerate() {
+            match self.coerce_row(row) {
+                Ok(row) => coerced.push(row),
+                Err(error) => errors.push(format!("Row {}: {}", row_index, error)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::TypeMismatch(errors.join("\n")));
+        }
+
+        let mut changes: BTreeMap<usize, RowChange> = BTreeMap::new();
+        for row in coerced {
+            let (row_index, previous) = match self.index {
+                Some(index) => self.upsert_row(index, row),
+                None => (self.push_row(row), None),
+            };
+            record_change(&mut changes, row_index, previous);
+        }
+        // new strings are ranked before the views compare them
+        for column in self.data.borrow_mut().iter_mut() {
+            column.sort_dictionary();
+        }
+        self.notify_views(changes);
+        Ok(())
+    }
+
+    // replaces the row with the same primary key, leaving any values which are missing
+    // from the update unchanged, or appends the row if the key is new. Returns the index
+    // of the row alongside its previous values.
+    fn upsert_row(
+        &mut self,
+        index: usize,
+        row: Vec<Option<CellValue>>,
+    ) -> (usize, Option<Vec<CellValue>>) {
+        let key = row[index].clone().unwrap_or(CellValue::Null);
+        match self.primary_keys.get(&key) {
+            Some(&row_index) => {
+                let previous = self.row_values(row_index);
+                let sequence = self.next_sequence();
+                let mut data = self.data.borrow_mut();
+                for (column, value) in data.iter_mut().zip(row) {
+                    if let Some(value) = value {
+                        column.set(row_index, &value);
+                    }
+                }
+                data[self.sequence_column()].set(row_index, &sequence);
+                (row_index, Some(previous))
+            }
+            None => {
+                let row_index = self.push_row(row);
+                self.primary_keys.insert(key, row_index);
+                (row_index, None)
+            }
+        }
+    }
+
+    fn push_row(&mut self, row: Vec<Option<CellValue>>) -> usize {
+        let sequence = self.next_sequence();
+        let mut data = self.data.borrow_mut();
+        for (column, value) in data.iter_mut().zip(row) {
+            column.push(&value.unwrap_or(CellValue::Null));
+        }
+        data[self.sequence_column()].push(&sequence);
+        data[self.insertion_column()].push(&sequence);
+        self.removed.push(false);
+        self.removed.len() - 1
+    }
+
+    fn next_sequence(&mut self) -> CellValue {
+        self.sequence += 1;
+        CellValue::Integer(self.sequence)
+    }
+
+    // coerces the values of a row, where columns missing from the row are None and
+    // columns that are not in the schema are ignored
+    fn coerce_row(&self, row: &Value) -> Result<Vec<Option<CellValue>>, String> {
+        let row = row
+            .as_object()
+            .ok_or("the elements of the array should be objects")?;
+
+        let mut errors: Vec<String> = vec![];
+        let values = self
+            .columns
+            .iter()
+            .zip(self.types.iter())
+            .map(|(col, column_type)| {
+                let value = row.get(col)?;
+                match CellValue::coerce(value, column_type) {
+                    Some(cell) => Some(cell),
+                    None => {
+                        errors.push(format!(
+                            "column '{}' expected {} but got {}",
+                            col, column_type, value
+                        ));
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<Option<CellValue>>>();
+
+        if let Some(index) = self.index {
+            match values[index] {
+                None | Some(CellValue::Null) => errors.push(format!(
+                    "missing a value for the index column '{}'",
+                    self.columns[index]
+                )),
+                _ => {}
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join(", "));
+        }
+        Ok(values)
     }
 }
 
 #[wasm_bindgen]
 impl Table {
     pub fn size(&self) -> usize {
-        self.data.len()
+        self.removed.iter().filter(|removed| !**removed).count()
     }
 
     pub fn columns(&self) -> String {
@@ -35,39 +347,178 @@ impl Table {
         self.columns.join(",")
     }
 
-    pub fn to_view(&mut self, config: &str) -> View {
-        View::new(self, config)
+    pub fn to_view(&mut self, config: &str) -> Result<View, JsValue> {
+        Ok(View::new(self, config)?)
+    }
+
+    // checks a view config before it is used to create a view, returning a list of
+    // problems which is empty if the config is valid
+    pub fn validate_view_config(&self, config: &str) -> Result<JsValue, JsValue> {
+        let problems = self.validate_view_config_with(config)?;
+        JsValue::from_serde(&problems).map_err(|error| JsValue::from(error.to_string().as_str()))
+    }
+
+    // appends the given rows, or upserts them if the table has an index
+    pub fn update(&mut self, json: JsValue) -> Result<(), JsValue> {
+        let array = parse_rows(json)?;
+        Ok(self.update_rows(&array)?)
+    }
+
+    // removes the rows with the given primary keys, the table must have an index
+    pub fn remove(&mut self, keys: JsValue) -> Result<(), JsValue> {
+        let keys = parse_rows(keys)?;
+        Ok(self.remove_rows(&keys)?)
     }
 
     #[wasm_bindgen(constructor)]
-    pub fn new(json: JsValue) -> Result<Table, JsValue> {
+    pub fn new(json: JsValue, index: Option<String>) -> Result<Table, JsValue> {
+        set_panic_hook();
+
+        let array = parse_rows(json)?;
+
+        // the schema is inferred from the rows, so there must be at least one
+        if array.is_empty() {
+            return Err(Error::EmptyInput(
+                "a table can't be created from an empty array, use from_schema instead".to_string(),
+            )
+            .into());
+        }
+
+        if !array.iter().all(|row| row.is_object()) {
+            return Err(Error::TypeMismatch(
+                "the elements of the array should be objects".to_string(),
+            )
+            .into());
+        }
+
+        // columns are collected from every row, not just the first
+        let columns: BTreeSet<&String> = array
+            .iter()
+            .filter_map(|row| row.as_object())
+            .flat_map(|row| row.keys())
+            .collect();
+
+        let schema = columns
+            .into_iter()
+            .map(|col| (col.clone(), ColumnType::infer(&array, col)))
+            .collect();
+
+        let mut table = Table::with_schema(schema, index)?;
+        table.update_rows(&array)?;
+        Ok(table)
+    }
+
+    // creates an empty table, where the schema maps column names to their types,
+    // e.g. {"x": "integer", "y": "string"}
+    pub fn from_schema(schema: JsValue, index: Option<String>) -> Result<Table, JsValue> {
         set_panic_hook();
 
-        let json_value: Value = json
+        let schema: BTreeMap<String, ColumnType> = schema
             .into_serde()
-            .map_err(|_| JsValue::from("JSON parse error"))?;
+            .map_err(|error| Error::InvalidConfig(format!("invalid schema, {}", error)))?;
 
-        let array = json_value
-            .as_array()
-            .ok_or(JsValue::from("Data should be an array"))?;
-        let rows = array.len();
+        Ok(Table::with_schema(schema.into_iter().collect(), index)?)
+    }
+}
 
-        let first_row = array[0]
-            .as_object()
-            .ok_or(JsValue::from("The elements of the array should be objects"))?;
+// records a change to a row, where a row that changes more than once keeps the values
+// it had before the first change
+fn record_change(
+    changes: &mut BTreeMap<usize, RowChange>,
+    row_index: usize,
+    previous: Option<Vec<CellValue>>,
+) -> &mut RowChange {
+    changes.entry(row_index).or_insert(RowChange {
+        row_index,
+        previous,
+        removed: false,
+    })
+}
 
-        let columns: Vec<String> = first_row.keys().map(|s| s.clone()).collect();
+fn parse_rows(json: JsValue) -> Result<Vec<Value>, Error> {
+    let json_value: Value = json
+        .into_serde()
+        .map_err(|error| Error::TypeMismatch(format!("the data isn't valid JSON, {}", error)))?;
 
-        let mut data: Vec<Vec<CellValue>> = vec![];
-        for row_index in 0..rows {
-            let row = columns
-                .iter()
-                .map(|col| &array[row_index][col])
-                .map(|x| CellValue::new(x))
-                .collect();
-            data.push(row);
-        }
+    match json_value {
+        Value::Array(array) => Ok(array),
+        _ => Err(Error::TypeMismatch(
+            "the data should be an array".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table() -> Table {
+        let schema = vec![
+            ("id".to_string(), ColumnType::Integer),
+            ("price".to_string(), ColumnType::Float),
+            ("name".to_string(), ColumnType::String),
+            ("active".to_string(), ColumnType::Boolean),
+            ("day".to_string(), ColumnType::Date),
+        ];
+        Table::with_schema(schema, Some("id".to_string())).unwrap()
+    }
+
+    #[test]
+    fn values_are_coerced_to_the_types_of_their_columns() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": "1", "price": 2, "name": 3, "active": "true", "day": "2008-08-24"}),
+                json!({"id": 2.0, "name": "b", "extra": "ignored"}),
+            ])
+            .unwrap();
+        assert_eq!(table.size(), 2);
+        assert_eq!(
+            table.row_values(0)[..5],
+            [
+                CellValue::Integer(1),
+                CellValue::Float(2.0),
+                CellValue::Str("3".to_string()),
+                CellValue::Bool(true),
+                CellValue::Date(1219536000000),
+            ]
+        );
+        // columns missing from a row are null
+        assert!(matches!(table.row_values(1)[1], CellValue::Null));
+    }
+
+    #[test]
+    fn rows_which_cannot_be_coerced_are_reported_and_none_are_applied() {
+        let mut table = table();
+        let error = table
+            .update_rows(&[
+                json!({"id": 1, "price": 1.5}),
+                json!({"id": 2, "price": "cheap", "active": 1}),
+                json!({"price": 3}),
+                json!([1, 2]),
+            ])
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::TypeMismatch(
+                [
+                    "Row 1: column 'price' expected float but got \"cheap\", column 'active' expected boolean but got 1",
+                    "Row 2: missing a value for the index column 'id'",
+                    "Row 3: the elements of the array should be objects",
+                ]
+                .join("\n")
+            )
+        );
+        assert_eq!(table.size(), 0);
+    }
 
-        return Ok(Table { columns, data });
+    #[test]
+    fn the_index_must_be_a_column_of_the_schema() {
+        let schema = vec![("id".to_string(), ColumnType::Integer)];
+        assert_eq!(
+            Table::with_schema(schema, Some("key".to_string())).err(),
+            Some(Error::UnknownColumn("key".to_string()))
+        );
     }
 }
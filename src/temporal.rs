@@ -0,0 +1,276 @@
+// dates and datetimes are both stored as milliseconds since the unix epoch (UTC),
+// with dates truncated to midnight
+pub const MILLIS_PER_DAY: i64 = 86_400_000;
+
+pub fn truncate_to_day(millis: i64) -> i64 {
+    millis.div_euclid(MILLIS_PER_DAY) * MILLIS_PER_DAY
+}
+
+// parses a date in the form YYYY-MM-DD
+pub fn parse_date(s: &str) -> Option<i64> {
+    let mut cursor = Cursor::new(s);
+    let days = cursor.date()?;
+    if !cursor.done() {
+        return None;
+    }
+    Some(days * MILLIS_PER_DAY)
+}
+
+// parses a date, optionally followed by a time of day and a UTC offset, e.g.
+// 2008-08-24, 2008-08-24T10:30:00Z, 2008-08-24 10:30:00.250+08:00
+// times without an offset are assumed to be UTC
+pub fn parse_datetime(s: &str) -> Option<i64> {
+    let mut cursor = Cursor::new(s);
+    let days = cursor.date()?;
+    if cursor.done() {
+        return Some(days * MILLIS_PER_DAY);
+    }
+    if !cursor.eat(b'T') && !cursor.eat(b' ') {
+        return None;
+    }
+    let time = cursor.time()?;
+    let offset = cursor.offset()?;
+    if !cursor.done() {
+        return None;
+    }
+    Some(days * MILLIS_PER_DAY + time - offset)
+}
+
+// formats a date as YYYY-MM-DD
+pub fn format_date(millis: i64) -> String {
+    let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// formats a datetime in UTC with millisecond precision, e.g. 2008-08-24T10:30:00.250Z
+pub fn format_datetime(millis: i64) -> String {
+    let time = millis.rem_euclid(MILLIS_PER_DAY);
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        format_date(millis),
+        time / 3_600_000,
+        time / 60_000 % 60,
+        time / 1000 % 60,
+        time % 1000
+    )
+}
+
+// the year, month and day of a number of days since 1970-01-01, the inverse of
+// days_from_civil, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+// days since 1970-01-01 in the proleptic gregorian calendar, see
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(s: &'a str) -> Cursor<'a> {
+        Cursor {
+            bytes: s.as_bytes(),
+            position: 0,
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn eat(&mut self, expected: u8) -> bool {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // reads exactly `count` decimal digits
+    fn digits(&mut self, count: usize) -> Option<i64> {
+        let mut value = 0;
+        for _ in 0..count {
+            let digit = self.peek().filter(u8::is_ascii_digit)?;
+            value = value * 10 + (digit - b'0') as i64;
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    // YYYY-MM-DD, returned as days since the epoch
+    fn date(&mut self) -> Option<i64> {
+        let year = self.digits(4)?;
+        if !self.eat(b'-') {
+            return None;
+        }
+        let month = self.digits(2)?;
+        if !self.eat(b'-') {
+            return None;
+        }
+        let day = self.digits(2)?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(days_from_civil(year, month, day))
+    }
+
+    // HH:MM[:SS[.fff]], returned as milliseconds since midnight
+    fn time(&mut self) -> Option<i64> {
+        let hours = self.digits(2)?;
+        if !self.eat(b':') {
+            return None;
+        }
+        let minutes = self.digits(2)?;
+        let mut seconds = 0;
+        let mut millis = 0;
+        if self.eat(b':') {
+            seconds = self.digits(2)?;
+            if self.eat(b'.') {
+                // only millisecond precision is kept, any further digits are ignored
+                let mut scale = 100;
+                let start = self.position;
+                while let Some(digit) = self.peek().filter(u8::is_ascii_digit) {
+                    millis += (digit - b'0') as i64 * scale;
+                    scale /= 10;
+                    self.position += 1;
+                }
+                if self.position == start {
+                    return None;
+                }
+            }
+        }
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return None;
+        }
+        Some(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis)
+    }
+
+    // Z, +HH:MM, +HHMM or +HH (or their negative equivalents), returned as milliseconds
+    // ahead of UTC. A missing offset is treated as UTC.
+    fn offset(&mut self) -> Option<i64> {
+        if self.done() || self.eat(b'Z') || self.eat(b'z') {
+            return Some(0);
+        }
+        let sign = if self.eat(b'+') {
+            1
+        } else if self.eat(b'-') {
+            -1
+        } else {
+            return None;
+        };
+        let hours = self.digits(2)?;
+        let minutes = if self.done() {
+            0
+        } else {
+            self.eat(b':');
+            self.digits(2)?
+        };
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+        Some(sign * (hours * 60 + minutes) * 60 * 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatted_dates_parse_to_the_same_values() {
+        for date in [
+            "1970-01-01",
+            "1969-12-31",
+            "2000-02-29",
+            "2008-08-24",
+            "1600-03-01",
+        ] {
+            assert_eq!(format_date(parse_date(date).unwrap()), date);
+        }
+        for datetime in ["2008-08-24T10:30:00.250Z", "1969-12-31T23:59:59.999Z"] {
+            assert_eq!(format_datetime(parse_datetime(datetime).unwrap()), datetime);
+        }
+    }
+
+    #[test]
+    fn datetimes_with_offsets_are_converted_to_utc() {
+        let utc = parse_datetime("2008-08-24T08:30:00Z").unwrap();
+        for datetime in [
+            "2008-08-24T10:30:00+02:00",
+            "2008-08-24T10:30:00+0200",
+            "2008-08-24T10:30:00+02",
+            "2008-08-24 03:00-05:30",
+            "2008-08-24T08:30:00.000z",
+            "2008-08-24T08:30:00",
+        ] {
+            assert_eq!(parse_datetime(datetime), Some(utc), "{}", datetime);
+        }
+        // only millisecond precision is kept
+        assert_eq!(
+            parse_datetime("2008-08-24T08:30:00.123456Z"),
+            Some(utc + 123)
+        );
+        // dates are midnight UTC
+        assert_eq!(parse_datetime("2008-08-24"), parse_date("2008-08-24"));
+        assert_eq!(truncate_to_day(utc), parse_date("2008-08-24").unwrap());
+    }
+
+    #[test]
+    fn invalid_dates_and_times_are_rejected() {
+        for datetime in [
+            "2008-02-30",
+            "2007-02-29",
+            "2008-13-01",
+            "2008-8-24",
+            "2008-08-24T24:00",
+            "2008-08-24T10:60",
+            "2008-08-24T10:30:00.",
+            "2008-08-24T10:30+2",
+            "2008-08-24T10:30:00+24:00",
+            "2008-08-24X10:30",
+            "2008-08-24T10:30:00Z and more",
+        ] {
+            assert_eq!(parse_datetime(datetime), None, "{}", datetime);
+        }
+        assert_eq!(parse_date("2008-08-24T10:30:00Z"), None);
+    }
+}
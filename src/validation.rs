@@ -0,0 +1,302 @@
+use super::config::{Aggregate, Config, Filter, FilterValue, Operation};
+use super::error::Error;
+use super::schema::ColumnType;
+use super::table::Table;
+
+// a problem with a view config, where `field` is the part of the config it was found
+// in (e.g. "row_pivots" or "aggregates") and `column` the column it concerns, so that
+// each bad field can be highlighted
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ConfigProblem {
+    pub field: String,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(field: &str, column: Option<&String>, error: Error) -> ConfigProblem {
+        ConfigProblem {
+            field: field.to_string(),
+            column: column.cloned(),
+            message: error.to_string(),
+        }
+    }
+}
+
+// checks a config against the columns of a table, returning every problem rather than
+// stopping at the first
+pub fn validate_config(table: &Table, config: &Config) -> Vec<ConfigProblem> {
+    let mut problems: Vec<ConfigProblem> = vec![];
+
+    for (field, columns) in [
+        ("columns", &config.columns),
+        ("row_pivots", &config.row_pivots),
+        ("column_pivots", &config.column_pivots),
+    ] {
+        for column in columns {
+            check_column(table, field, column, &mut problems);
+        }
+    }
+
+    for sort in &config.sort {
+        check_column(table, "sort", &sort.column, &mut problems);
+    }
+
+    check_filters(table, "filter", &config.filter, true, &mut problems);
+
+    // sorted so that the problems come out in the same order each time
+    let mut aggregates: Vec<_> = config.aggregates.iter().collect();
+    aggregates.sort_by(|a, b| a.0.cmp(b.0));
+    for (column, aggregate) in aggregates {
+        let column_type = match check_column(table, "aggregates", column, &mut problems) {
+            Some(column_type) => column_type,
+            None => continue,
+        };
+        if !aggregate.accepts(column_type) {
+            problems.push(ConfigProblem::new(
+                "aggregates",
+                Some(column),
+                Error::TypeMismatch(format!(
+                    "the {} aggregate can't be applied to column '{}', which is {}",
+                    serde_json::to_string(aggregate).unwrap_or_default(),
+                    column,
+                    column_type
+                )),
+            ));
+        }
+        if let Aggregate::WeightedMean(weights) = aggregate {
+            match check_column(table, "aggregates", weights, &mut problems) {
+                Some(ColumnType::Integer) | Some(ColumnType::Float) | None => {}
+                Some(weights_type) => problems.push(ConfigProblem::new(
+                    "aggregates",
+                    Some(column),
+                    Error::TypeMismatch(format!(
+                        "column '{}' is {} and can't be used as weights",
+                        weights, weights_type
+                    )),
+                )),
+            }
+        }
+    }
+
+    // having filters are compared with the aggregated values, whose types depend on the
+    // aggregates, so only their columns are checked
+    let depths = config.row_pivots.len();
+    for having in &config.having {
+        if having.depth < 1 || having.depth > depths {
+            problems.push(ConfigProblem::new(
+                "having",
+                None,
+                Error::InvalidConfig(format!(
+                    "having depth {} is out of range, the row pivots have depths 1 to {}",
+                    having.depth, depths
+                )),
+            ));
+        }
+        check_filters(table, "having", &having.filter, false, &mut problems);
+    }
+
+    problems
+}
+
+// records a problem if the column isn't in the table, otherwise returns its type
+fn check_column(
+    table: &Table,
+    field: &str,
+    column: &String,
+    problems: &mut Vec<ConfigProblem>,
+) -> Option<ColumnType> {
+    match table.index_for_column(column) {
+        Ok(index) => Some(table.types[index]),
+        Err(error) => {
+            problems.push(ConfigProblem::new(field, Some(column), error));
+            None
+        }
+    }
+}
+
+fn check_filters(
+    table: &Table,
+    field: &str,
+    filters: &[Filter],
+    check_values: bool,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    for filter in filters {
+        match filter {
+            Filter::Descriptor(desc) => {
+                let column_type = check_column(table, field, &desc.column, problems);
+                if let (Some(column_type), true) = (column_type, check_values) {
+                    if let Err(error) =
+                        check_filter_value(&desc.column, column_type, desc.operation, &desc.value)
+                    {
+                        problems.push(ConfigProblem::new(field, Some(&desc.column), error));
+                    }
+                }
+            }
+            Filter::Group(group) => {
+                check_filters(table, field, &group.filters, check_values, problems)
+            }
+        }
+    }
+}
+
+// whether the value of a filter can be compared with the values of its column, where
+// a missing value is allowed as the filter may still be being edited
+fn check_filter_value(
+    column: &str,
+    column_type: ColumnType,
+    operation: Operation,
+    value: &FilterValue,
+) -> Result<(), Error> {
+    let mismatch = |message: String| Err(Error::TypeMismatch(message));
+    let values = match (operation, value) {
+        (Operation::IsNull, _) | (Operation::IsNotNull, _) => return Ok(()),
+        (Operation::Contains, _) | (Operation::BeginsWith, _) | (Operation::EndsWith, _)
+            if column_type != ColumnType::String =>
+        {
+            return mismatch(format!(
+                "column '{}' is {} but text filters need a string column",
+                column, column_type
+            ));
+        }
+        (Operation::In, _) | (Operation::NotIn, _) => value.values(),
+        (_, FilterValue::List(_)) => {
+            return mismatch(format!(
+                "the filter on column '{}' expects a single value but got a list",
+                column
+            ));
+        }
+        (_, FilterValue::Value(value)) => std::slice::from_ref(value),
+    };
+    match values
+        .iter()
+        .find(|value| !value.is_null() && !value.is_comparable_with(column_type))
+    {
+        Some(value) => mismatch(format!(
+            "column '{}' is {} and can't be compared with {}",
+            column,
+            column_type,
+            serde_json::to_string(value).unwrap_or_default()
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn table() -> Table {
+        let schema = vec![
+            ("id".to_string(), ColumnType::Integer),
+            ("country".to_string(), ColumnType::String),
+            ("medals".to_string(), ColumnType::Integer),
+            ("day".to_string(), ColumnType::Date),
+        ];
+        Table::with_schema(schema, Some("id".to_string())).unwrap()
+    }
+
+    // the fields and columns of the problems with a config, which is valid unless
+    // overridden by the given fields
+    fn problems(fields: Value) -> Vec<(String, Option<String>)> {
+        let mut config = json!({
+            "columns": ["country", "medals"],
+            "row_pivots": ["country"],
+            "aggregates": {"medals": "sum", "country": "distinct count"},
+            "sort": [{"column": "medals", "order": "desc"}],
+            "filter": [["day", ">", "2008-08-08"]],
+            "having": [{"depth": 1, "filter": [["medals", ">", 10]]}],
+        });
+        for (field, value) in fields.as_object().unwrap() {
+            config[field] = value.clone();
+        }
+        let config = Config::new(config.to_string()).unwrap();
+        validate_config(&table(), &config)
+            .into_iter()
+            .map(|problem| (problem.field, problem.column))
+            .collect()
+    }
+
+    fn problem(field: &str, column: Option<&str>) -> (String, Option<String>) {
+        (field.to_string(), column.map(|column| column.to_string()))
+    }
+
+    #[test]
+    fn every_unknown_column_is_reported() {
+        assert_eq!(problems(json!({})), vec![]);
+        assert_eq!(
+            problems(json!({
+                "columns": ["country", "year"],
+                "row_pivots": ["sport"],
+                "column_pivots": ["event"],
+                "sort": [{"column": "gold", "order": "asc"}],
+                "filter": [{"filter_op": "or", "filters": [["silver", "==", 1]]}],
+                "aggregates": {"bronze": "sum"},
+                "having": [{"depth": 1, "filter": [["total", ">", 10]]}],
+            })),
+            vec![
+                problem("columns", Some("year")),
+                problem("row_pivots", Some("sport")),
+                problem("column_pivots", Some("event")),
+                problem("sort", Some("gold")),
+                problem("filter", Some("silver")),
+                problem("aggregates", Some("bronze")),
+                problem("having", Some("total")),
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregates_must_suit_the_types_of_their_columns() {
+        assert_eq!(
+            problems(json!({
+                "aggregates": {
+                    "country": "sum",
+                    "medals": ["weighted mean", "day"],
+                    "day": "high",
+                },
+            })),
+            vec![
+                problem("aggregates", Some("country")),
+                problem("aggregates", Some("medals")),
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_values_must_be_comparable_with_their_columns() {
+        assert_eq!(
+            problems(json!({
+                "filter": [
+                    ["medals", "==", "gold"],
+                    ["country", "begins with", "U"],
+                    ["medals", "contains", "1"],
+                    ["medals", "in", [1, "2"]],
+                    ["country", "==", ["US", "UK"]],
+                    ["day", "<", "not a date"],
+                    ["day", "is null"],
+                ],
+            })),
+            vec![
+                problem("filter", Some("medals")),
+                problem("filter", Some("medals")),
+                problem("filter", Some("medals")),
+                problem("filter", Some("country")),
+                problem("filter", Some("day")),
+            ]
+        );
+    }
+
+    #[test]
+    fn having_depths_must_be_those_of_the_row_pivots() {
+        assert_eq!(
+            problems(json!({"having": [
+                {"depth": 0, "filter": []},
+                {"depth": 2, "filter": [["medals", ">", "many"]]},
+            ]})),
+            vec![problem("having", None), problem("having", None)]
+        );
+    }
+}
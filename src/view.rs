@@ -1,17 +1,88 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
 use super::accumulator::Accumulator;
-use super::config::{Config, FilterDescriptor};
-use super::pivot_table::PivotTable;
-use super::table::Table;
+use super::cell_value::CellValue;
+use super::column::Column;
+use super::config::Config;
+use super::error::Error;
+use super::filter::FilterExpr;
+use super::pivot_table::{PivotTable, SerializableColumnarPivotTable, SerializablePivotTable};
+use super::table::{RowChange, Table};
 
 #[wasm_bindgen]
 pub struct View {
+    state: Rc<RefCell<ViewState>>,
+}
+
+// the parts of a view which are shared with the table it was created from, so that the
+// table can keep them up to date as its rows change
+pub struct ViewState {
+    data: Rc<RefCell<Vec<Column>>>,
     pivot_table: PivotTable,
     config: Config,
+    filter: FilterExpr,
+    listeners: Vec<UpdateListener>,
+    next_listener_id: usize,
+}
+
+pub type UpdateCallback = Rc<dyn Fn(&ViewUpdate)>;
 
-    pub num_rows: usize,
-    pub num_columns: usize,
+// whether an update callback is sent the rows which changed, as with Perspective's
+// on_update(callback, {mode: "row"})
+#[derive(Copy, Clone, PartialEq)]
+pub enum UpdateMode {
+    None,
+    Row,
+}
+
+impl UpdateMode {
+    pub fn new(mode: Option<String>) -> Result<UpdateMode, Error> {
+        match mode.as_deref() {
+            None | Some("none") => Ok(UpdateMode::None),
+            Some("row") => Ok(UpdateMode::Row),
+            Some(mode) => Err(Error::InvalidConfig(format!(
+                "unknown update mode '{}'",
+                mode
+            ))),
+        }
+    }
+}
+
+// sent to the update callbacks after each update of the table
+#[derive(Serialize)]
+pub struct ViewUpdate {
+    // the rows which were added or changed, in the same shape as to_columns
+    pub delta: Option<SerializableColumnarPivotTable>,
+}
+
+struct UpdateListener {
+    id: usize,
+    mode: UpdateMode,
+    callback: UpdateCallback,
+    // the JS function the callback wraps, so that it can later be removed
+    function: Option<js_sys::Function>,
+}
+
+// the callbacks to run once an update has been applied, which happens after the view
+// has been released so that the callbacks are free to query it
+pub struct PendingUpdate {
+    callbacks: Vec<(UpdateMode, UpdateCallback)>,
+    delta: Option<SerializableColumnarPivotTable>,
+}
+
+impl PendingUpdate {
+    pub fn notify(self) {
+        let update = ViewUpdate { delta: None };
+        let row_update = ViewUpdate { delta: self.delta };
+        for (mode, callback) in self.callbacks {
+            match mode {
+                UpdateMode::None => callback(&update),
+                UpdateMode::Row => callback(&row_update),
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -23,86 +94,1001 @@ pub struct ViewOptions {
 }
 
 impl ViewOptions {
-    pub fn new(options_string: String) -> ViewOptions {
-        let options: ViewOptions = serde_json::from_str(options_string.as_str()).unwrap();
-        return options;
+    pub fn new(options_string: String) -> Result<ViewOptions, Error> {
+        serde_json::from_str(options_string.as_str())
+            .map_err(|error| Error::InvalidConfig(error.to_string()))
     }
 }
 
-#[wasm_bindgen]
-impl View {
-    #[wasm_bindgen(skip)]
-    pub fn new(table: &Table, config: &str) -> View {
-        let config = Config::new(config.to_string());
+impl ViewState {
+    // updates the view to reflect changes to the rows of the table
+    pub fn apply(&mut self, changes: &[RowChange]) -> PendingUpdate {
+        let data = self.data.borrow();
 
-        // create tuples with column indices alongside filters
-        let keyed_filters = config
-            .filter
+        // rows which were previously in the view are removed, then re-added if they still
+        // satisfy the filters
+        let removed: Vec<(usize, &Vec<CellValue>)> = changes
+            .iter()
+            .filter_map(|change| match &change.previous {
+                Some(previous) if self.filter.matches(previous) => {
+                    Some((change.row_index, previous))
+                }
+                _ => None,
+            })
+            .collect();
+        let added: Vec<usize> = changes
             .iter()
-            .map(|s| table.index_for_column(&s.column))
-            .zip(config.filter.iter())
-            .collect::<Vec<(usize, &FilterDescriptor)>>();
+            .filter(|change| !change.removed && self.filter.matches_row(&data, change.row_index))
+            .map(|change| change.row_index)
+            .collect();
 
-        let mut filtered_table = Table {
-            data: table
-                .data
+        let updated = self.pivot_table.update(&data, &removed, &added);
+
+        // the delta is only computed if a callback asks for it
+        let delta = if self
+            .listeners
+            .iter()
+            .any(|listener| listener.mode == UpdateMode::Row)
+        {
+            Some(
+                self.pivot_table
+                    .to_serializable_delta(&data, &updated, &self.config.columns),
+            )
+        } else {
+            None
+        };
+        PendingUpdate {
+            callbacks: self
+                .listeners
                 .iter()
-                .filter(|row| {
-                    keyed_filters.iter().all(|(col_index, filter)| {
-                        row[*col_index].matches(&filter.operation, &filter.value)
-                    })
-                })
-                .cloned()
+                .map(|listener| (listener.mode, listener.callback.clone()))
                 .collect(),
-            // TODO - do we need to keep copying these values
-            columns: table.columns.iter().cloned().collect(),
-        };
+            delta,
+        }
+    }
 
-        let accumulators: Vec<Accumulator> = table
+    // follows the rows of the table as they are renumbered, given the new index of
+    // each row
+    pub fn renumber_rows(&mut self, row_indices: &[usize]) {
+        self.pivot_table.renumber_rows(row_indices);
+    }
+
+    fn add_listener(
+        &mut self,
+        callback: UpdateCallback,
+        mode: UpdateMode,
+        function: Option<js_sys::Function>,
+    ) -> usize {
+        let id = self.next_listener_id;
+        self.next_listener_id += 1;
+        self.listeners.push(UpdateListener {
+            id,
+            mode,
+            callback,
+            function,
+        });
+        id
+    }
+}
+
+#[wasm_bindgen]
+impl View {
+    #[wasm_bindgen(skip)]
+    pub fn new(table: &mut Table, config: &str) -> Result<View, Error> {
+        let config = Config::new(config.to_string())?;
+
+        let filter = FilterExpr::new(table, &config.filter, config.filter_op)?;
+
+        let accumulators = table
             .columns
             .iter()
             .map(|col| match config.aggregates.get(col) {
-                Some(agg) => Accumulator::from_aggregate(agg),
-                None => Accumulator::Noop,
+                Some(agg) => Accumulator::from_aggregate(agg, table),
+                None => Ok(Accumulator::Noop),
             })
-            .collect();
+            .collect::<Result<Vec<Accumulator>, Error>>()?;
+
+        let mut rows: Vec<usize> = table.row_indices();
+        filter.filter(&table.data.borrow(), &mut rows);
 
         let pivot_table = PivotTable::new(
-            &mut filtered_table,
+            table,
+            &rows,
             &config.row_pivots,
+            &config.column_pivots,
             &config.sort,
             &accumulators,
-        );
+            &config.having,
+        )?;
 
-        return View {
+        let state = Rc::new(RefCell::new(ViewState {
+            data: table.data.clone(),
             pivot_table,
-            num_rows: table.size(),
-            num_columns: table.columns.len(),
+            filter,
             config,
-        };
+            listeners: vec![],
+            next_listener_id: 0,
+        }));
+        table.register_view(&state);
+        Ok(View { state })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn num_rows(&self) -> usize {
+        self.state.borrow().pivot_table.num_rows()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn num_columns(&self) -> usize {
+        // with column pivots, each of the columns is repeated for each split
+        let state = self.state.borrow();
+        let data = state.data.borrow();
+        state
+            .pivot_table
+            .column_names(&data, &state.config.columns)
+            .len()
     }
 
-    pub fn to_columns(&self, options: &str) -> JsValue {
-        let options = ViewOptions::new(options.to_string());
-        JsValue::from_serde(
-            &self
-                .pivot_table
-                .to_serializable_columns(&self.config.columns, &options),
-        )
-        .unwrap()
+    pub fn to_columns(&self, options: &str) -> Result<JsValue, JsValue> {
+        let columns = self.to_columns_with(options)?;
+        JsValue::from_serde(&columns).map_err(|error| JsValue::from(error.to_string().as_str()))
     }
 
-    pub fn to_json(&self) -> JsValue {
-        JsValue::from_serde(&self.pivot_table.to_serializable_rows()).unwrap()
+    pub fn to_json(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.to_rows())
+            .map_err(|error| JsValue::from(error.to_string().as_str()))
+    }
+
+    // expands the groups of a pivoted view down to the given depth, collapsing the rest
+    pub fn set_depth(&self, depth: usize) {
+        self.state.borrow_mut().pivot_table.set_depth(depth);
+    }
+
+    // expands the group shown at the given row, so that its children are shown
+    pub fn expand(&self, row: usize) {
+        self.state.borrow_mut().pivot_table.set_expanded(row, true);
+    }
+
+    // collapses the group shown at the given row, hiding its descendants
+    pub fn collapse(&self, row: usize) {
+        self.state.borrow_mut().pivot_table.set_expanded(row, false);
+    }
+
+    pub fn get_row_expanded(&self, row: usize) -> bool {
+        self.state.borrow().pivot_table.is_expanded(row)
+    }
+
+    // registers a JS function which is called after each update of the table, where
+    // mode "row" also sends the rows which changed
+    pub fn on_update(
+        &self,
+        callback: js_sys::Function,
+        mode: Option<String>,
+    ) -> Result<(), JsValue> {
+        let mode = UpdateMode::new(mode)?;
+        let function = callback.clone();
+        let wrapper: UpdateCallback = Rc::new(move |update: &ViewUpdate| {
+            let result = JsValue::from_serde(update)
+                .map_err(|error| JsValue::from(error.to_string().as_str()))
+                .and_then(|update| function.call1(&JsValue::NULL, &update));
+            if let Err(error) = result {
+                web_sys::console::error_1(&error);
+            }
+        });
+        self.state
+            .borrow_mut()
+            .add_listener(wrapper, mode, Some(callback));
+        Ok(())
+    }
+
+    pub fn remove_update(&self, callback: js_sys::Function) {
+        self.state
+            .borrow_mut()
+            .listeners
+            .retain(|listener| listener.function.as_ref() != Some(&callback));
     }
 
     pub fn columns(&self) -> String {
+        let state = self.state.borrow();
+        let data = state.data.borrow();
+        let config = &state.config;
         // wasm-bindgen cannot return vectors!
         // https://github.com/rustwasm/wasm-bindgen/issues/111
-        let mut foo = self.config.columns.join(",");
-        if self.config.row_pivots.len() > 0 {
+        let mut foo = state
+            .pivot_table
+            .column_names(&data, &config.columns)
+            .join(",");
+        if config.row_pivots.len() > 0 {
             foo.insert_str(0, "__ROW_PATH__,");
         }
         return foo;
     }
 }
+
+impl View {
+    // the window of the view given by the options, in the same shape as to_columns
+    pub fn to_columns_with(&self, options: &str) -> Result<SerializableColumnarPivotTable, Error> {
+        let options = ViewOptions::new(options.to_string())?;
+        let state = self.state.borrow();
+        let data = state.data.borrow();
+        Ok(state
+            .pivot_table
+            .to_serializable_columns(&data, &state.config.columns, &options))
+    }
+
+    // every row of the view, in the same shape as to_json
+    pub fn to_rows(&self) -> SerializablePivotTable {
+        let state = self.state.borrow();
+        let data = state.data.borrow();
+        state.pivot_table.to_serializable_rows(&data)
+    }
+
+    // registers a closure which is called after each update of the table, returning an
+    // id which can be passed to remove_update_with
+    pub fn on_update_with(
+        &self,
+        callback: impl Fn(&ViewUpdate) + 'static,
+        mode: UpdateMode,
+    ) -> usize {
+        self.state
+            .borrow_mut()
+            .add_listener(Rc::new(callback), mode, None)
+    }
+
+    pub fn remove_update_with(&self, id: usize) {
+        self.state
+            .borrow_mut()
+            .listeners
+            .retain(|listener| listener.id != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ColumnType;
+    use serde_json::{json, Value};
+
+    fn table() -> Table {
+        let schema = vec![
+            ("id".to_string(), ColumnType::Integer),
+            ("country".to_string(), ColumnType::String),
+            ("medals".to_string(), ColumnType::Integer),
+        ];
+        Table::with_schema(schema, Some("id".to_string())).unwrap()
+    }
+
+    fn config(row_pivots: &[&str]) -> String {
+        json!({
+            "columns": ["id", "country", "medals"],
+            "row_pivots": row_pivots,
+            "aggregates": {"medals": "sum", "id": "count"},
+            "sort": [{"column": "medals", "order": "desc"}],
+            "filter": [["medals", ">", 0]],
+        })
+        .to_string()
+    }
+
+    fn rows(view: &View) -> Value {
+        serde_json::to_value(view.to_rows()).unwrap()
+    }
+
+    // checks that views which were kept up to date show the same rows as new views
+    fn assert_matches_new_views(table: &mut Table, views: &[(View, String)]) {
+        for (view, config) in views {
+            let new_view = View::new(table, config).unwrap();
+            assert_eq!(rows(view), rows(&new_view), "{}", config);
+            assert_eq!(view.columns(), new_view.columns(), "{}", config);
+        }
+    }
+
+    #[test]
+    fn removed_rows_are_dropped_from_the_table_and_its_views() {
+        let mut table = table();
+        let rows: Vec<Value> = (0..10)
+            .map(|id| {
+                let country = ["US", "UK", "FR"][id % 3];
+                json!({"id": id, "country": country, "medals": id})
+            })
+            .collect();
+        table.update_rows(&rows).unwrap();
+        let views: Vec<(View, String)> = [config(&[]), config(&["country"])]
+            .iter()
+            .map(|config| (View::new(&mut table, config).unwrap(), config.clone()))
+            .collect();
+
+        table
+            .remove_rows(&(0..8).map(|id| json!(id)).collect::<Vec<Value>>())
+            .unwrap();
+        assert_eq!(table.data.borrow()[0].len(), 2);
+        assert_eq!(table.size(), 2);
+        assert_matches_new_views(&mut table, &views);
+
+        table
+            .update_rows(&[
+                json!({"id": 9, "medals": 20}),
+                json!({"id": 3, "country": "US", "medals": 3}),
+            ])
+            .unwrap();
+        assert_eq!(table.size(), 3);
+        assert_matches_new_views(&mut table, &views);
+    }
+
+    #[test]
+    fn updated_views_match_new_views() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "US", "medals": 2}),
+                json!({"id": 4, "country": "FR", "medals": 7}),
+            ])
+            .unwrap();
+        // groups which are split by the id, and hidden unless they have enough medals
+        let mut split = serde_json::from_str::<Value>(&config(&["country"])).unwrap();
+        split["column_pivots"] = json!(["id"]);
+        split["having"] = json!([{"depth": 1, "filter": [["medals", ">", 2]]}]);
+        let views: Vec<(View, String)> = [
+            config(&[]),
+            config(&["country"]),
+            config(&["country", "id"]),
+            split.to_string(),
+        ]
+        .iter()
+        .map(|config| (View::new(&mut table, config).unwrap(), config.clone()))
+        .collect();
+
+        // an upsert which moves a row to another group
+        table
+            .update_rows(&[json!({"id": 3, "country": "UK", "medals": 4})])
+            .unwrap();
+        assert_matches_new_views(&mut table, &views);
+
+        // a remove which empties a group
+        table.remove_rows(&[json!(4)]).unwrap();
+        assert_matches_new_views(&mut table, &views);
+
+        // a row with a new key, and one which no longer matches the filter
+        table
+            .update_rows(&[
+                json!({"id": 5, "country": "DE", "medals": 1}),
+                json!({"id": 1, "medals": 0}),
+            ])
+            .unwrap();
+        assert_matches_new_views(&mut table, &views);
+
+        // an update which hides every group
+        table
+            .update_rows(&[json!({"id": 2, "medals": 1}), json!({"id": 3, "medals": 1})])
+            .unwrap();
+        assert_matches_new_views(&mut table, &views);
+    }
+
+    #[test]
+    fn missing_values_are_skipped_by_the_aggregates() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 1}),
+                json!({"id": 2, "country": "US"}),
+                json!({"id": 3, "country": "US", "medals": 3}),
+                json!({"id": 4, "country": "UK"}),
+            ])
+            .unwrap();
+        let aggregates = [
+            ("sum", json!([4, 4, null])),
+            ("count", json!([2, 2, 0])),
+            ("avg", json!([2.0, 2.0, null])),
+            ("low", json!([1, 1, null])),
+            ("high", json!([3, 3, null])),
+        ];
+        for (aggregate, expected) in aggregates.iter() {
+            let config = json!({
+                "columns": ["medals"],
+                "row_pivots": ["country"],
+                "aggregates": {"medals": aggregate},
+                "sort": [{"column": "country", "order": "desc"}],
+                "filter": [],
+            });
+            let view = View::new(&mut table, &config.to_string()).unwrap();
+            let columns = view
+                .to_columns_with(r#"{"start_row": 0, "end_row": 3, "start_col": 0, "end_col": 1}"#)
+                .unwrap();
+            let columns = serde_json::to_value(columns).unwrap();
+            assert_eq!(&columns["columns"]["medals"], expected, "{}", aggregate);
+        }
+    }
+
+    #[test]
+    fn first_follows_inserts_and_last_follows_writes() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 10}),
+                json!({"id": 2, "country": "US", "medals": 20}),
+            ])
+            .unwrap();
+        let config = |aggregate: &str| {
+            json!({
+                "columns": ["medals"],
+                "row_pivots": ["country"],
+                "aggregates": {"medals": aggregate},
+                "sort": [],
+                "filter": [],
+            })
+            .to_string()
+        };
+        let first = View::new(&mut table, &config("first")).unwrap();
+        let last = View::new(&mut table, &config("last")).unwrap();
+        let medals = |view: &View| rows(view)["rows"][1]["medals"].clone();
+        assert_eq!(medals(&first), json!(10));
+        assert_eq!(medals(&last), json!(20));
+
+        table
+            .update_rows(&[json!({"id": 1, "medals": 11})])
+            .unwrap();
+        assert_eq!(medals(&first), json!(11));
+        assert_eq!(medals(&last), json!(11));
+    }
+
+    #[test]
+    fn having_filters_compare_percentages_of_the_whole_table() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 1}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "FR", "medals": 6}),
+            ])
+            .unwrap();
+        let config = json!({
+            "columns": ["country", "medals"],
+            "row_pivots": ["country"],
+            "aggregates": {"medals": "pct sum grand total"},
+            "sort": [{"column": "medals", "order": "desc"}],
+            "filter": [],
+            "having": [{"depth": 1, "filter": [["medals", ">", 20]]}],
+        })
+        .to_string();
+        let view = View::new(&mut table, &config).unwrap();
+        let medals = |view: &View| -> Vec<Value> {
+            rows(view)["rows"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|row| row["medals"].clone())
+                .collect()
+        };
+        // the shares are of all of the rows, including those of the hidden groups
+        assert_eq!(medals(&view), vec![json!(90.0), json!(60.0), json!(30.0)]);
+
+        table.update_rows(&[json!({"id": 1, "medals": 5})]).unwrap();
+        assert_eq!(
+            medals(&view),
+            vec![
+                json!(100.0),
+                json!(42.857142857142854),
+                json!(35.714285714285715),
+                json!(21.428571428571427)
+            ]
+        );
+        assert_matches_new_views(&mut table, &[(view, config)]);
+    }
+
+    #[test]
+    fn rows_which_end_before_they_start_are_empty() {
+        let mut table = table();
+        table
+            .update_rows(&[json!({"id": 1, "country": "US", "medals": 1})])
+            .unwrap();
+        for row_pivots in [&[][..], &["country"][..]] {
+            let view = View::new(&mut table, &config(row_pivots)).unwrap();
+            let columns = view
+                .to_columns_with(r#"{"start_row": 2, "end_row": 1, "start_col": 0, "end_col": 3}"#)
+                .unwrap();
+            let columns = serde_json::to_value(columns).unwrap();
+            assert_eq!(columns["row_paths"], json!([]));
+        }
+    }
+
+    #[test]
+    fn num_columns_counts_each_split_column() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 1}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+            ])
+            .unwrap();
+        let mut config = serde_json::from_str::<Value>(&config(&["id"])).unwrap();
+        config["columns"] = json!(["medals"]);
+        config["column_pivots"] = json!(["country"]);
+        let view = View::new(&mut table, &config.to_string()).unwrap();
+        assert_eq!(view.columns(), "__ROW_PATH__,UK|medals,US|medals");
+        assert_eq!(view.num_columns(), 2);
+
+        table
+            .update_rows(&[json!({"id": 3, "country": "FR", "medals": 2})])
+            .unwrap();
+        assert_eq!(view.num_columns(), 3);
+    }
+
+    #[test]
+    fn sums_which_overflow_are_promoted_to_floats() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": i64::MAX}),
+                json!({"id": 2, "country": "UK", "medals": 1}),
+            ])
+            .unwrap();
+        let view = View::new(&mut table, &config(&["country"])).unwrap();
+        let medals = |view: &View| -> Vec<Value> {
+            rows(view)["rows"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|row| row["medals"].clone())
+                .collect()
+        };
+        // the groups are merged into the grand total
+        let total = i64::MAX as f64 + 1.0;
+        assert_eq!(medals(&view), vec![json!(total), json!(i64::MAX), json!(1)]);
+
+        // the rows are summed within a group
+        table
+            .update_rows(&[json!({"id": 2, "country": "US"})])
+            .unwrap();
+        assert_eq!(medals(&view), vec![json!(total), json!(total)]);
+    }
+
+    #[test]
+    fn shares_of_groups_outside_an_update_follow_the_totals() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "UK", "medals": 5}),
+                json!({"id": 3, "country": "FR", "medals": 10}),
+            ])
+            .unwrap();
+        let views: Vec<(View, String)> = ["pct sum grand total", "pct sum parent"]
+            .iter()
+            .map(|aggregate| {
+                let config = json!({
+                    "columns": ["medals"],
+                    "row_pivots": ["country", "id"],
+                    "aggregates": {"medals": aggregate},
+                    "sort": [],
+                    "filter": [],
+                    "having": [{"depth": 2, "filter": [["medals", ">", 20]]}],
+                })
+                .to_string();
+                (View::new(&mut table, &config).unwrap(), config)
+            })
+            .collect();
+        let countries = |view: &View| -> Vec<Value> {
+            rows(view)["row_paths"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|path| path["values"].as_array().unwrap().len() == 1)
+                .map(|path| path["values"][0].clone())
+                .collect()
+        };
+        assert_eq!(
+            countries(&views[0].0),
+            vec![json!("FR"), json!("UK"), json!("US")]
+        );
+
+        // the shares of the other countries fall, so that they're hidden
+        table
+            .update_rows(&[json!({"id": 3, "medals": 30})])
+            .unwrap();
+        assert_eq!(countries(&views[0].0), vec![json!("FR")]);
+        assert_matches_new_views(&mut table, &views);
+
+        // then rise, so that they're shown again
+        table.update_rows(&[json!({"id": 3, "medals": 5})]).unwrap();
+        assert_eq!(
+            countries(&views[0].0),
+            vec![json!("FR"), json!("UK"), json!("US")]
+        );
+        assert_matches_new_views(&mut table, &views);
+    }
+
+    #[test]
+    fn groups_move_among_their_siblings_as_their_values_change() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "FR", "medals": 7}),
+                json!({"id": 4, "country": "DE", "medals": 3}),
+            ])
+            .unwrap();
+        let view = View::new(&mut table, &config(&["country"])).unwrap();
+        let countries = |view: &View| -> Vec<Value> {
+            rows(view)["row_paths"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .skip(1)
+                .map(|path| path["values"][0].clone())
+                .collect()
+        };
+        // groups with equal values are ordered by their keys
+        assert_eq!(
+            countries(&view),
+            vec![json!("FR"), json!("US"), json!("DE"), json!("UK")]
+        );
+
+        table
+            .update_rows(&[json!({"id": 2, "medals": 10})])
+            .unwrap();
+        assert_eq!(
+            countries(&view),
+            vec![json!("UK"), json!("FR"), json!("US"), json!("DE")]
+        );
+
+        table.remove_rows(&[json!(3)]).unwrap();
+        table
+            .update_rows(&[json!({"id": 5, "country": "IT", "medals": 4})])
+            .unwrap();
+        assert_eq!(
+            countries(&view),
+            vec![json!("UK"), json!("US"), json!("IT"), json!("DE")]
+        );
+    }
+
+    #[test]
+    fn having_filters_hide_groups_and_their_descendants_from_the_totals() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "US", "medals": 1}),
+                json!({"id": 3, "country": "UK", "medals": 2}),
+                json!({"id": 4, "country": "FR", "medals": 4}),
+                json!({"id": 5, "country": "FR", "medals": 3}),
+            ])
+            .unwrap();
+        let config = |having: Value| {
+            let mut config = serde_json::from_str::<Value>(&config(&["country", "id"])).unwrap();
+            config["columns"] = json!(["medals"]);
+            config["having"] = having;
+            config.to_string()
+        };
+        // the paths of the groups with their medals
+        let groups = |view: &View| -> Vec<(Value, Value)> {
+            let rows = rows(view);
+            rows["row_paths"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .zip(rows["rows"].as_array().unwrap())
+                .map(|(path, row)| (path["values"].clone(), row["medals"].clone()))
+                .collect()
+        };
+
+        // the ids with too few medals are hidden, and so is the country without any others
+        let ids = config(json!([{"depth": 2, "filter": [["medals", ">", 2]]}]));
+        let view = View::new(&mut table, &ids).unwrap();
+        assert_eq!(
+            groups(&view),
+            vec![
+                (json!([]), json!(12)),
+                (json!(["FR"]), json!(7)),
+                (json!(["FR", 4]), json!(4)),
+                (json!(["FR", 5]), json!(3)),
+                (json!(["US"]), json!(5)),
+                (json!(["US", 1]), json!(5)),
+            ]
+        );
+
+        // the countries are filtered on their totals, and both filters can be combined
+        let countries = config(json!([
+            {"depth": 1, "filter": [["medals", ">=", 6]]},
+            {"depth": 2, "filter": [["medals", ">", 2]]},
+        ]));
+        let view = View::new(&mut table, &countries).unwrap();
+        assert_eq!(
+            groups(&view),
+            vec![
+                (json!([]), json!(7)),
+                (json!(["FR"]), json!(7)),
+                (json!(["FR", 4]), json!(4)),
+                (json!(["FR", 5]), json!(3)),
+            ]
+        );
+
+        // groups are shown again once they match
+        let views = vec![
+            (View::new(&mut table, &ids).unwrap(), ids),
+            (view, countries),
+        ];
+        table
+            .update_rows(&[json!({"id": 2, "medals": 3}), json!({"id": 3, "medals": 6})])
+            .unwrap();
+        assert_eq!(groups(&views[1].0)[0], (json!([]), json!(21)));
+        assert_matches_new_views(&mut table, &views);
+    }
+
+    #[test]
+    fn column_pivots_split_each_column_by_the_values_of_the_split_columns() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "US", "medals": 2}),
+            ])
+            .unwrap();
+        let mut config = serde_json::from_str::<Value>(&config(&["id"])).unwrap();
+        config["columns"] = json!(["medals", "id"]);
+        config["column_pivots"] = json!(["country"]);
+        config["sort"] = json!([]);
+        let view = View::new(&mut table, &config.to_string()).unwrap();
+        assert_eq!(
+            view.columns(),
+            "__ROW_PATH__,UK|medals,UK|id,US|medals,US|id"
+        );
+        let columns = |view: &View| {
+            let options = r#"{"start_row": 0, "end_row": 10, "start_col": 0, "end_col": 4}"#;
+            let columns = view.to_columns_with(options).unwrap();
+            serde_json::to_value(columns).unwrap()["columns"].clone()
+        };
+        // the groups without any rows for a split have no values in its columns
+        assert_eq!(
+            columns(&view),
+            json!({
+                "UK|medals": [3, null, 3, null],
+                "UK|id": [1, null, 1, null],
+                "US|medals": [7, 5, null, 2],
+                "US|id": [2, 1, null, 1],
+            })
+        );
+
+        // each combination of the values of several split columns is named by its path
+        config["row_pivots"] = json!([]);
+        config["columns"] = json!(["medals"]);
+        config["column_pivots"] = json!(["country", "id"]);
+        let view = View::new(&mut table, &config.to_string()).unwrap();
+        assert_eq!(view.columns(), "UK|2|medals,US|1|medals,US|3|medals");
+        assert_eq!(
+            columns(&view),
+            json!({
+                "UK|2|medals": [null, 3, null],
+                "US|1|medals": [5, null, null],
+                "US|3|medals": [null, null, 2],
+            })
+        );
+    }
+
+    #[test]
+    fn groups_can_be_expanded_and_collapsed() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "US", "medals": 2}),
+                json!({"id": 4, "country": "FR", "medals": 4}),
+            ])
+            .unwrap();
+        let view = View::new(&mut table, &config(&["country", "id"])).unwrap();
+        let paths = |view: &View| -> Vec<Value> {
+            let columns = view
+                .to_columns_with(
+                    r#"{"start_row": 0, "end_row": 100, "start_col": 0, "end_col": 3}"#,
+                )
+                .unwrap();
+            let paths = serde_json::to_value(columns).unwrap()["row_paths"].clone();
+            assert_eq!(paths.as_array().unwrap().len(), view.num_rows());
+            paths
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|path| path["values"].clone())
+                .collect()
+        };
+        // every group is expanded to begin with
+        assert_eq!(view.num_rows(), 8);
+        assert!(view.get_row_expanded(1));
+        assert!(!view.get_row_expanded(2));
+
+        view.set_depth(0);
+        assert_eq!(
+            paths(&view),
+            vec![json!([]), json!(["US"]), json!(["FR"]), json!(["UK"])]
+        );
+        assert!(view.get_row_expanded(0));
+        assert!(!view.get_row_expanded(1));
+
+        view.expand(2);
+        assert_eq!(
+            paths(&view),
+            vec![
+                json!([]),
+                json!(["US"]),
+                json!(["FR"]),
+                json!(["FR", 4]),
+                json!(["UK"])
+            ]
+        );
+        assert!(view.get_row_expanded(2));
+        // the grand total, leaves and rows out of range can't be expanded or collapsed
+        view.collapse(0);
+        view.expand(3);
+        view.expand(10);
+        assert_eq!(view.num_rows(), 5);
+        assert!(!view.get_row_expanded(3));
+        assert!(!view.get_row_expanded(10));
+
+        // groups stay expanded as they move, and new groups follow the depth
+        table
+            .update_rows(&[json!({"id": 5, "country": "DE", "medals": 10})])
+            .unwrap();
+        assert_eq!(
+            paths(&view),
+            vec![
+                json!([]),
+                json!(["DE"]),
+                json!(["US"]),
+                json!(["FR"]),
+                json!(["FR", 4]),
+                json!(["UK"])
+            ]
+        );
+        view.collapse(3);
+        assert_eq!(view.num_rows(), 5);
+
+        // setting the depth again forgets which groups were expanded or collapsed
+        view.expand(1);
+        view.set_depth(0);
+        assert_eq!(view.num_rows(), 5);
+        view.set_depth(1);
+        assert_eq!(view.num_rows(), 10);
+    }
+
+    #[test]
+    fn groups_are_sorted_by_their_aggregates_at_each_depth() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": 5}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "US", "medals": 2}),
+                json!({"id": 4, "country": "FR", "medals": 4}),
+                json!({"id": 6, "country": "FR", "medals": 1}),
+            ])
+            .unwrap();
+        let mut paths = |sort: Value| -> Vec<Value> {
+            let mut config = serde_json::from_str::<Value>(&config(&["country", "id"])).unwrap();
+            config["sort"] = sort;
+            let view = View::new(&mut table, &config.to_string()).unwrap();
+            rows(&view)["row_paths"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .skip(1)
+                .map(|path| path["values"].clone())
+                .collect()
+        };
+        assert_eq!(
+            paths(json!([{"column": "medals", "order": "asc"}])),
+            vec![
+                json!(["UK"]),
+                json!(["UK", 2]),
+                json!(["FR"]),
+                json!(["FR", 6]),
+                json!(["FR", 4]),
+                json!(["US"]),
+                json!(["US", 3]),
+                json!(["US", 1]),
+            ]
+        );
+
+        // the counts of the ids tie, so the groups are ordered by the next sort
+        assert_eq!(
+            paths(json!([
+                {"column": "id", "order": "desc"},
+                {"column": "medals", "order": "desc"},
+            ])),
+            vec![
+                json!(["US"]),
+                json!(["US", 1]),
+                json!(["US", 3]),
+                json!(["FR"]),
+                json!(["FR", 4]),
+                json!(["FR", 6]),
+                json!(["UK"]),
+                json!(["UK", 2]),
+            ]
+        );
+
+        // the groups of a sorted pivot column are ordered by their keys, and the groups
+        // which tie on every sort are in ascending order of their keys
+        assert_eq!(
+            paths(json!([{"column": "country", "order": "desc"}])),
+            vec![
+                json!(["US"]),
+                json!(["US", 1]),
+                json!(["US", 3]),
+                json!(["UK"]),
+                json!(["UK", 2]),
+                json!(["FR"]),
+                json!(["FR", 4]),
+                json!(["FR", 6]),
+            ]
+        );
+    }
+
+    #[test]
+    fn abs_sorts_order_by_magnitude_and_col_sorts_order_the_split_columns() {
+        let mut table = table();
+        table
+            .update_rows(&[
+                json!({"id": 1, "country": "US", "medals": -5}),
+                json!({"id": 2, "country": "UK", "medals": 3}),
+                json!({"id": 3, "country": "US", "medals": -1}),
+                json!({"id": 4, "country": "FR", "medals": 4}),
+            ])
+            .unwrap();
+        let mut view = |row_pivots: &[&str], column_pivots: &[&str], order: &str| {
+            let mut config = serde_json::from_str::<Value>(&config(row_pivots)).unwrap();
+            config["columns"] = json!(["medals"]);
+            config["column_pivots"] = json!(column_pivots);
+            config["sort"] = json!([{"column": "medals", "order": order}]);
+            config["filter"] = json!([]);
+            View::new(&mut table, &config.to_string()).unwrap()
+        };
+        let medals = |view: &View| -> Vec<Value> {
+            rows(view)["rows"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|row| row["medals"].clone())
+                .collect()
+        };
+        assert_eq!(
+            medals(&view(&[], &[], "desc abs")),
+            vec![json!(-5), json!(4), json!(3), json!(-1)]
+        );
+        assert_eq!(
+            medals(&view(&[], &[], "asc abs")),
+            vec![json!(-1), json!(3), json!(4), json!(-5)]
+        );
+        assert_eq!(
+            medals(&view(&["country"], &[], "desc abs")),
+            vec![json!(1), json!(-6), json!(4), json!(3)]
+        );
+
+        // the split columns are ordered by their totals, leaving the rows in key order
+        let sorted = view(&["country"], &["id"], "col desc");
+        assert_eq!(
+            sorted.columns(),
+            "__ROW_PATH__,4|medals,2|medals,3|medals,1|medals"
+        );
+        let countries: Vec<Value> = rows(&sorted)["row_paths"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|path| path["values"].clone())
+            .collect();
+        assert_eq!(
+            countries,
+            vec![json!([]), json!(["FR"]), json!(["UK"]), json!(["US"])]
+        );
+        assert_eq!(
+            view(&["country"], &["id"], "col asc abs").columns(),
+            "__ROW_PATH__,3|medals,2|medals,4|medals,1|medals"
+        );
+        assert_eq!(
+            view(&[], &["id"], "col asc").columns(),
+            "1|medals,3|medals,2|medals,4|medals"
+        );
+    }
+}
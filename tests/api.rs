@@ -0,0 +1,49 @@
+//! Tests of the api for use from rust, outside of the wasm bindings.
+
+extern crate perspective_rs;
+extern crate serde_json;
+
+use perspective_rs::{ColumnType, Error, Table, UpdateMode, View};
+use serde_json::json;
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[test]
+fn tables_and_views_can_be_used_from_rust() {
+    let schema = vec![
+        ("country".to_string(), ColumnType::String),
+        ("medals".to_string(), ColumnType::Integer),
+    ];
+    let mut table = Table::with_schema(schema, Some("country".to_string())).unwrap();
+    table
+        .update_rows(&[
+            json!({"country": "US", "medals": 3}),
+            json!({"country": "UK", "medals": 2}),
+        ])
+        .unwrap();
+
+    let config = json!({
+        "columns": ["country", "medals"],
+        "row_pivots": [],
+        "aggregates": {},
+        "sort": [{"column": "medals", "order": "desc"}],
+        "filter": [],
+    });
+    let view = View::new(&mut table, &config.to_string()).unwrap();
+    let updates = Rc::new(Cell::new(0));
+    let counter = updates.clone();
+    view.on_update_with(move |_| counter.set(counter.get() + 1), UpdateMode::Row);
+
+    table.remove_rows(&[json!("UK")]).unwrap();
+    assert_eq!(updates.get(), 1);
+    let columns = view
+        .to_columns_with(r#"{"start_row": 0, "end_row": 10, "start_col": 0, "end_col": 2}"#)
+        .unwrap();
+    let columns = serde_json::to_value(columns).unwrap();
+    assert_eq!(columns["columns"]["country"], json!(["US"]));
+
+    let mut config = config;
+    config["row_pivots"] = json!(["year"]);
+    let error = View::new(&mut table, &config.to_string()).err();
+    assert_eq!(error, Some(Error::UnknownColumn("year".to_string())));
+}
@@ -4,9 +4,16 @@ import computedFunctions from "./computed-functions";
 import View from "./view";
 
 export default class Table {
-  constructor(schema, data) {
+  constructor(schema, data, options = {}) {
     this._data = data;
-    this._adaptee = new wasm.Table(data);
+    // the columns are typed by the schema, so that the table can start out empty,
+    // otherwise their types are inferred from the data
+    if (schema) {
+      this._adaptee = wasm.Table.from_schema(schema, options.index);
+      this._adaptee.update(data || []);
+    } else {
+      this._adaptee = new wasm.Table(data, options.index);
+    }
     this._schema = schema;
     this.type = "table";
   }
@@ -32,28 +39,28 @@ export default class Table {
   }
 
   view(config) {
-    config = {
-      columns: [],
-      aggregates: [],
-      column_pivots: [],
-      computed_columns: [],
-      filter: config.filter
-        ? config.filter
-            .map(filter => {
-              if (filter[1] === "==") {
-                filter[1] = "eqeq";
-              }
-            })
-        : [],
-      row_pivots: [],
-      sort: [],
-      ...config
-    };
-    config.filter = config.filter.filter(f => f[2] !== null)
+    config = withDefaults(config);
+    // filters which are still being edited have no value, other than the null checks,
+    // while groups of filters ({filter_op, filters}) are passed through as they are
+    config.filter = config.filter.filter(
+      filter =>
+        !Array.isArray(filter) ||
+        filter[2] !== null ||
+        filter[1] === "is null" ||
+        filter[1] === "is not null"
+    );
     const view = this._adaptee.to_view(JSON.stringify(config));
     return new View(config, view, this);
   }
 
+  // resolves to a list of {field, column, message} problems, empty if the config is valid
+  validate_view_config(config) {
+    const problems = this._adaptee.validate_view_config(
+      JSON.stringify(withDefaults(config))
+    );
+    return Promise.resolve(problems);
+  }
+
   get_computed_functions() {
     return computedFunctions;
   }
@@ -62,7 +69,32 @@ export default class Table {
     return this._adaptee.size();
   }
 
+  update(data) {
+    this._adaptee.update(data);
+    return Promise.resolve();
+  }
+
+  remove(keys) {
+    this._adaptee.remove(keys);
+    return Promise.resolve();
+  }
+
   is_valid_filter() {
     return Promise.resolve(true);
   }
 }
+
+// split_by is the newer name for column_pivots, which is mapped onto it here as the
+// wasm module won't accept both
+function withDefaults({ split_by, ...config }) {
+  return {
+    columns: [],
+    aggregates: [],
+    column_pivots: split_by || [],
+    computed_columns: [],
+    filter: [],
+    row_pivots: [],
+    sort: [],
+    ...config
+  };
+}
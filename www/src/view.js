@@ -1,8 +1,15 @@
+const toColumns = cols => ({
+  ...cols.columns,
+  __ROW_PATH__: cols.row_paths.map(s => s.values)
+});
+
 export default class View {
   constructor(config, adaptee, table) {
     this._config = config;
     this._adaptee = adaptee;
     this._table = table;
+    // the wrapped callbacks, so that they can be removed
+    this._callbacks = new Map();
   }
 
   get_config() {
@@ -11,9 +18,39 @@ export default class View {
 
   delete() {}
 
-  on_update() {}
+  on_update(callback, options = {}) {
+    const wrapped = update =>
+      callback({ delta: update.delta && toColumns(update.delta) });
+    this._callbacks.set(callback, wrapped);
+    this._adaptee.on_update(wrapped, options.mode);
+  }
+
+  remove_update(callback) {
+    const wrapped = this._callbacks.get(callback);
+    if (wrapped) {
+      this._adaptee.remove_update(wrapped);
+      this._callbacks.delete(callback);
+    }
+  }
+
+  set_depth(depth) {
+    this._adaptee.set_depth(depth);
+    return Promise.resolve();
+  }
 
-  remove_update() {}
+  expand(row) {
+    this._adaptee.expand(row);
+    return Promise.resolve();
+  }
+
+  collapse(row) {
+    this._adaptee.collapse(row);
+    return Promise.resolve();
+  }
+
+  get_row_expanded(row) {
+    return Promise.resolve(this._adaptee.get_row_expanded(row));
+  }
 
   num_rows() {
     return Promise.resolve(this._adaptee.num_rows);
@@ -33,11 +70,7 @@ export default class View {
     options.start_col = Math.ceil(options.start_col);
     options.start_row = Math.ceil(options.start_row);
     const cols = this._adaptee.to_columns(JSON.stringify(options));
-    const ret = {
-      ...cols.columns,
-      __ROW_PATH__: cols.row_paths.map(s => s.values)
-    };
-    return Promise.resolve(ret);
+    return Promise.resolve(toColumns(cols));
   }
 
   to_json() {